@@ -1,77 +1,532 @@
-use crate::path::expand_env_var;
+use crate::host;
+use crate::path::{expand_env_var, expand_var};
+use crate::when;
 use anyhow::ensure;
-use anyhow::{bail, Ok, Result};
+use anyhow::{bail, Context as _, Ok, Result};
+use core::fmt::{self, Display};
+use indexmap::map::Entry;
 use indexmap::IndexMap;
-use reqwest;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 use std::path::PathBuf;
-use url::Url;
 
-#[derive(Debug, Serialize, Deserialize)]
+mod diff;
+mod discover;
+mod edit;
+mod env;
+mod format;
+mod http;
+mod local;
+mod migrate;
+mod source;
+mod span;
+mod validate;
+mod verify;
+
+pub use diff::{Change, Difference};
+pub use discover::{
+    app_config_path, discover_app_config, ConfigFile, ConfigFiles, Layer, PROJECT_CONFIG_FILE_NAMES,
+};
+pub use env::{CONFIG_ENV, DOTFILES_ENV, REPOS_ENV};
+pub use format::{Format, CONFIG_FILE_NAMES};
+pub use http::{HttpOptions, TOKEN_ENV};
+pub use local::local_path;
+pub use migrate::CURRENT_VERSION;
+pub use source::{ConfigSource, Location};
+pub use span::Position;
+pub use validate::{validate_app_config, Diagnostic, Severity};
+pub use verify::Verification;
+
+/// Path for each OS.
+///
+/// The path of the running OS is looked up in the following order.
+/// - Windows: `windows`, `default`
+/// - macOS: `mac`, `unix`, `default`
+/// - Linux: `linux`, `unix`, `default`
+/// - WSL: `wsl`, `linux`, `unix`, `default`
+/// - FreeBSD: `freebsd`, `unix`, `default`
+/// - OpenBSD: `openbsd`, `unix`, `default`
+///
+/// Variables are expanded in the path.
+/// - Windows: `%NAME%`. Known folders such as `%FOLDERID_RoamingAppData%` are also available.
+/// - Others: `${NAME}`. `${XDG_CONFIG_HOME}` and `${XDG_DATA_HOME}` are available even if not set.
+///
+/// NAME is a variable defined in `vars` or an environment variable.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct OsPath {
+    /// Path on Windows.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub windows: Option<String>,
+    /// Path on macOS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub mac: Option<String>,
+    /// Path on Linux.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub linux: Option<String>,
+    /// Path on WSL (Windows Subsystem for Linux). Takes precedence over `linux`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wsl: Option<String>,
+    /// Path on FreeBSD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub freebsd: Option<String>,
+    /// Path on OpenBSD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub openbsd: Option<String>,
+    /// Path on Unix-like OS (Linux, macOS and BSDs).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix: Option<String>,
+    /// Path on any OS.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
 }
+
+cfg_if::cfg_if! {
+    if #[cfg(target_os = "windows")] {
+        const OS_NAME: &str = "Windows";
+        const OS_KEYS: &[&str] = &["windows", "default"];
+    } else if #[cfg(target_os = "macos")] {
+        const OS_NAME: &str = "Mac";
+        const OS_KEYS: &[&str] = &["mac", "unix", "default"];
+    } else if #[cfg(target_os = "linux")] {
+        const OS_NAME: &str = "Linux";
+        const OS_KEYS: &[&str] = &["linux", "unix", "default"];
+    } else if #[cfg(target_os = "freebsd")] {
+        const OS_NAME: &str = "FreeBSD";
+        const OS_KEYS: &[&str] = &["freebsd", "unix", "default"];
+    } else if #[cfg(target_os = "openbsd")] {
+        const OS_NAME: &str = "OpenBSD";
+        const OS_KEYS: &[&str] = &["openbsd", "unix", "default"];
+    } else {
+        const OS_NAME: &str = "Unix";
+        const OS_KEYS: &[&str] = &["unix", "default"];
+    }
+}
+
+const WSL_KEYS: &[&str] = &["wsl", "linux", "unix", "default"];
+
+/// Keys of OsPath looked up on the running OS, in order of precedence.
+pub(crate) fn os_keys(is_wsl: bool) -> &'static [&'static str] {
+    if cfg!(target_os = "linux") && is_wsl {
+        WSL_KEYS
+    } else {
+        OS_KEYS
+    }
+}
+
 impl OsPath {
     pub fn to_pathbuf(&self) -> Result<PathBuf> {
-        #[cfg(target_os = "windows")]
-        {
-            let Some(path) = &self.windows else {
-                bail!("Windows Path not defined.");
-            };
-            Ok(PathBuf::from(expand_env_var(path)?))
+        self.to_pathbuf_with(&IndexMap::new())
+    }
+
+    /// Path on the running OS, expanding user defined variables `vars` and environment variables.
+    pub fn to_pathbuf_with(&self, vars: &IndexMap<String, String>) -> Result<PathBuf> {
+        let Some(path) = self.lookup(os_keys(host::is_wsl())) else {
+            bail!("{} Path not defined.", OS_NAME);
+        };
+        Ok(PathBuf::from(expand_var(path, |name| {
+            Ok(vars.get(name).cloned())
+        })?))
+    }
+
+    /// Key of the path used on the running OS.
+    pub(crate) fn os_key(&self) -> Option<&'static str> {
+        os_keys(host::is_wsl())
+            .iter()
+            .copied()
+            .find(|key| self.get(key).is_some())
+    }
+
+    /// First path defined in `keys`.
+    fn lookup(&self, keys: &[&str]) -> Option<&String> {
+        keys.iter().find_map(|key| self.get(key).as_ref())
+    }
+
+    fn get(&self, key: &str) -> &Option<String> {
+        match key {
+            "windows" => &self.windows,
+            "mac" => &self.mac,
+            "linux" => &self.linux,
+            "wsl" => &self.wsl,
+            "freebsd" => &self.freebsd,
+            "openbsd" => &self.openbsd,
+            "unix" => &self.unix,
+            "default" => &self.default,
+            _ => &None,
         }
+    }
 
-        #[cfg(target_os = "macos")]
-        {
-            let Some(path) = &self.mac else {
-                bail!("Mac Path not defined.");
-            };
-            Ok(PathBuf::from(expand_env_var(path)?))
+    /// Set the path of the key, e.g. `linux`, `unix` or `default`.
+    pub fn set(&mut self, key: &str, path: String) -> Result<()> {
+        let value = match key {
+            "windows" => &mut self.windows,
+            "mac" => &mut self.mac,
+            "linux" => &mut self.linux,
+            "wsl" => &mut self.wsl,
+            "freebsd" => &mut self.freebsd,
+            "openbsd" => &mut self.openbsd,
+            "unix" => &mut self.unix,
+            "default" => &mut self.default,
+            _ => bail!(
+                "Unknown OS {}. Use windows, mac, linux, wsl, freebsd, openbsd, unix or default.",
+                key
+            ),
+        };
+        *value = Some(path);
+        Ok(())
+    }
+}
+
+/// How files are deployed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployMode {
+    /// Symbolic link to the file in the repository.
+    #[default]
+    Symlink,
+    /// Copy of the file. Changes of the copy are reported as modified.
+    Copy,
+    /// Hard link to the file in the repository. Directories can not be hard linked.
+    Hardlink,
+}
+
+impl DeployMode {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Deploy target. Destination path for each OS and options.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DeployTarget {
+    #[serde(flatten)]
+    pub path: OsPath,
+    /// Condition to deploy the target. The target is skipped if it is false.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    /// How files are deployed. `symlink` if not set.
+    #[serde(default, skip_serializing_if = "DeployMode::is_default")]
+    pub mode: DeployMode,
+    /// Create symbolic links relative to the destination directory.
+    /// `relative_links` of the config file if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub relative_links: Option<bool>,
+    /// Descend into directories existing at the destination and deploy only files,
+    /// instead of conflicting with them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub recursive: bool,
+}
+
+impl From<OsPath> for DeployTarget {
+    fn from(path: OsPath) -> Self {
+        Self {
+            path,
+            ..Self::default()
         }
+    }
+}
 
-        #[cfg(target_os = "linux")]
-        {
-            let Some(path) = &self.linux else {
-                bail!("Linux Path not defined.");
-            };
-            Ok(PathBuf::from(expand_env_var(path)?))
+impl DeployTarget {
+    /// Reason to skip the target. `None` if the target is deployed.
+    pub fn skip_reason(&self, context: &when::Context) -> Result<Option<String>> {
+        skip_reason(&self.when, context)
+    }
+}
+
+/// Reason to skip by the condition. `None` if the condition is not set or true.
+fn skip_reason(condition: &Option<String>, context: &when::Context) -> Result<Option<String>> {
+    match condition {
+        Some(condition) if !when::evaluate(condition, context)? => {
+            Ok(Some(format!("when `{}` is false", condition)))
         }
+        _ => Ok(None),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Dotfiles repository.
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
 pub struct Repository {
+    /// Local repository name. The repository is downloaded to this directory under `dotfiles`.
     pub name: String,
-    pub url: String,
-    pub deploy: IndexMap<String, OsPath>,
+    /// Git repository url.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Local directory used instead of a git repository. Absolute, or relative to `dotfiles`.
+    /// The directory is deployed as is, and never cloned or updated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Directory used instead of `dotfiles` for this repository.
+    /// `dotfiles` is used on OS the directory is not defined for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<OsPath>,
+    /// Branch to check out. Updated by fast-forward.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Tag to check out as detached HEAD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Commit to check out as detached HEAD.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// Condition to use the repository. The repository is skipped if it is false.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+    /// Deploy targets.
+    /// Files and directories directly under the directory of the key are deployed to the destination.
+    pub deploy: IndexMap<String, DeployTarget>,
+    /// Deploy overrides for each host name pattern.
+    /// Entries of matching patterns are added to `deploy`, or replace the entry with the same name.
+    /// An entry without path (`~`) removes the entry.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub hosts: IndexMap<String, IndexMap<String, Option<DeployTarget>>>,
+}
+
+/// Git ref a repository is pinned to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pin<'a> {
+    Branch(&'a str),
+    Tag(&'a str),
+    Rev(&'a str),
+}
+
+/// Where the files of a repository come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source<'a> {
+    /// Git repository url.
+    Url(&'a str),
+    /// Local directory.
+    Path(&'a str),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+impl Display for Source<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Url(url) => write!(f, "{}", url),
+            Source::Path(path) => write!(f, "{} (local)", path),
+        }
+    }
+}
+
+impl Display for Pin<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pin::Branch(branch) => write!(f, "branch {}", branch),
+            Pin::Tag(tag) => write!(f, "tag {}", tag),
+            Pin::Rev(rev) => write!(f, "rev {}", rev),
+        }
+    }
+}
+
+impl Repository {
+    /// Source of the repository. Exactly one of `url` and `path` must be set.
+    pub fn source(&self) -> Result<Source<'_>> {
+        match (&self.url, &self.path) {
+            (Some(url), None) => Ok(Source::Url(url)),
+            (None, Some(path)) => Ok(Source::Path(path)),
+            (Some(_), Some(_)) => bail!(
+                "Only one of url and path can be set for repository \"{}\".",
+                self.name
+            ),
+            (None, None) => bail!(
+                "Either url or path must be set for repository \"{}\".",
+                self.name
+            ),
+        }
+    }
+
+    /// Directory of the repository.
+    /// `path` is resolved from the root if relative, otherwise the directory is `name` under the root.
+    /// The root is `dir` if defined for the running OS, otherwise `dotfiles`.
+    pub fn to_pathbuf_with(
+        &self,
+        dotfiles: &Path,
+        vars: &IndexMap<String, String>,
+    ) -> Result<PathBuf> {
+        let root = match self.dir.as_ref().filter(|dir| dir.os_key().is_some()) {
+            Some(dir) => dir.to_pathbuf_with(vars)?,
+            None => dotfiles.to_path_buf(),
+        };
+        match &self.path {
+            Some(path) => Ok(root.join(expand_var(path, |name| Ok(vars.get(name).cloned()))?)),
+            None => Ok(root.join(&self.name)),
+        }
+    }
+
+    /// Git ref the repository is pinned to. Only one of `branch`, `tag` and `rev` can be set.
+    pub fn pin(&self) -> Result<Option<Pin<'_>>> {
+        let pins = [
+            self.branch.as_deref().map(Pin::Branch),
+            self.tag.as_deref().map(Pin::Tag),
+            self.rev.as_deref().map(Pin::Rev),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+        ensure!(
+            pins.len() <= 1,
+            "Only one of branch, tag and rev can be set for repository \"{}\".",
+            self.name
+        );
+        ensure!(
+            pins.is_empty() || self.path.is_none(),
+            "Local repository \"{}\" cannot be pinned to {}.",
+            self.name,
+            pins[0]
+        );
+        Ok(pins.first().copied())
+    }
+
+    /// Reason to skip the repository. `None` if the repository is used.
+    pub fn skip_reason(&self, context: &when::Context) -> Result<Option<String>> {
+        skip_reason(&self.when, context)
+    }
+
+    /// Host name patterns that match the host name, in the order of definition.
+    pub fn host_rules(&self, hostname: &str) -> Vec<&str> {
+        self.hosts
+            .keys()
+            .filter(|pattern| host::matches(pattern, hostname))
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Deploy targets for the host name.
+    /// Host rules are applied in the order of definition, so later rules take precedence.
+    pub fn resolve_deploy(&self, hostname: &str) -> IndexMap<&str, &DeployTarget> {
+        let mut deploy = self
+            .deploy
+            .iter()
+            .map(|(name, path)| (name.as_str(), path))
+            .collect::<IndexMap<_, _>>();
+        for pattern in self.host_rules(hostname) {
+            for (name, path) in &self.hosts[pattern] {
+                if let Some(path) = path {
+                    deploy.insert(name.as_str(), path);
+                } else {
+                    deploy.shift_remove(name.as_str());
+                }
+            }
+        }
+        deploy
+    }
+}
+
+/// rrcm configuration file.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct AppConfig {
+    /// Version of config file. Older config files are migrated by `rrcm config migrate`.
+    #[serde(default)]
+    pub version: u32,
+    /// Config files whose repositories are merged into this config.
+    /// Relative paths are resolved from the directory of the including file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// User defined variables usable in every path.
+    /// Values can refer to environment variables and other variables.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub vars: IndexMap<String, String>,
+    /// Directory where repositories are downloaded.
     pub dotfiles: OsPath,
+    /// Create symbolic links relative to the destination directory, instead of absolute paths.
+    /// Links keep working when the home directory is moved or mounted at another path.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub relative_links: bool,
+    /// Repositories. Multiple repositories can be specified.
     pub repos: Vec<Repository>,
 }
 
+/// Config file included by `include`.
+/// Only `repos` and nested `include` are used.
+#[derive(Debug, Deserialize)]
+struct IncludedConfig {
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    repos: Vec<Repository>,
+}
+
 impl Default for AppConfig {
     fn default() -> Self {
         let dotfiles = OsPath {
             windows: Some("%USERPROFILE%\\dotfiles".to_string()),
             mac: Some("${HOME}/.dotfiles".to_string()),
             linux: Some("${HOME}/.dotfiles".to_string()),
+            ..OsPath::default()
         };
 
         let repos = Vec::new();
-        Self { dotfiles, repos }
+        Self {
+            version: CURRENT_VERSION,
+            include: Vec::new(),
+            vars: IndexMap::new(),
+            dotfiles,
+            relative_links: false,
+            repos,
+        }
     }
 }
 
 impl AppConfig {
     pub fn to_pathbuf(&self) -> Result<PathBuf> {
-        self.dotfiles.to_pathbuf()
+        self.dotfiles.to_pathbuf_with(&self.resolve_vars()?)
+    }
+
+    /// Expand user defined variables.
+    /// Variables referring to each other are expanded recursively, and cycles are errors.
+    pub fn resolve_vars(&self) -> Result<IndexMap<String, String>> {
+        let mut resolved = IndexMap::new();
+        for name in self.vars.keys() {
+            resolve_var(name, &self.vars, &mut resolved, &mut Vec::new())?;
+        }
+        Ok(resolved)
+    }
+
+    /// Repositories to operate on. The repository of `name`, the ones listed in `RRCM_REPOS`,
+    /// or all repositories if neither is given.
+    pub fn select_repos(&self, name: &Option<String>) -> Vec<&Repository> {
+        let Some(names) = env::selected_repos(name.as_deref()) else {
+            return self.repos.iter().collect();
+        };
+        for unknown in names
+            .iter()
+            .filter(|n| !self.repos.iter().any(|repo| repo.name == **n))
+        {
+            log::warn!("Repository {} is not found.", unknown);
+        }
+        self.repos
+            .iter()
+            .filter(|repo| names.contains(&repo.name))
+            .collect()
+    }
+}
+
+fn resolve_var(
+    name: &str,
+    vars: &IndexMap<String, String>,
+    resolved: &mut IndexMap<String, String>,
+    stack: &mut Vec<String>,
+) -> Result<Option<String>> {
+    if let Some(value) = resolved.get(name) {
+        return Ok(Some(value.clone()));
     }
+    let Some(value) = vars.get(name) else {
+        return Ok(None);
+    };
+    if stack.iter().any(|n| n == name) {
+        bail!(
+            "Variable cycle detected: {} -> {}",
+            stack.join(" -> "),
+            name
+        );
+    }
+
+    stack.push(name.to_string());
+    let expanded = expand_var(value, |n| resolve_var(n, vars, resolved, stack))
+        .with_context(|| format!("Failed to expand variable {}", name))?;
+    stack.pop();
+
+    resolved.insert(name.to_string(), expanded.clone());
+    Ok(Some(expanded))
 }
 
 pub fn init_app_config<P>(path: P) -> Result<()>
@@ -89,11 +544,20 @@ where
         }
     }
     let config = AppConfig::default();
-    confy::store_path(path, &config)?;
+    std::fs::write(path, Format::from_path(path).serialize(&config)?)?;
     Ok(())
 }
 
-pub fn download_app_config<P>(path: P, url: Url) -> Result<()>
+/// Initialize the config file from the location, verifying its integrity.
+/// Nothing is written unless the content is verified and parsed as a config file
+/// in the format of `path`.
+/// The location and digest are recorded in [`ConfigSource`], except for stdin.
+pub fn download_app_config<P>(
+    path: P,
+    location: &Location,
+    verification: &Verification,
+    http: &HttpOptions,
+) -> Result<()>
 where
     P: AsRef<Path>,
 {
@@ -102,28 +566,538 @@ where
         !path.exists(),
         format!("{} already exists.", path.display())
     );
-    let res = reqwest::blocking::get(url)?.error_for_status()?;
+    let content = location.read(http)?;
+    let sha256 = verification
+        .verify(&content, |extension| {
+            location.with_extension(extension)?.read(http)
+        })
+        .with_context(|| format!("Failed to verify {}", location))?;
+    check_app_config(&content, Format::from_path(path))
+        .with_context(|| format!("{} is not a valid config file.", location))?;
+
     if let Some(parent) = path.parent() {
         if !parent.exists() {
             std::fs::create_dir_all(parent)?;
         }
     }
-    std::fs::write(path, res.bytes()?)?;
+    std::fs::write(path, content)?;
+    if let Some(source) = ConfigSource::new(location, sha256, verification)? {
+        source.write(path)?;
+    }
     Ok(())
 }
 
+/// Check the content is a config file of the format.
+fn check_app_config(content: &[u8], format: Format) -> Result<()> {
+    normalize_app_config(content, format)?;
+    Ok(())
+}
+
+/// Parse the content as a config file of the format, and return the document
+/// migrated to the current version with defaults filled.
+fn normalize_app_config(content: &[u8], format: Format) -> Result<serde_yml::Value> {
+    let mut document = format.parse(std::str::from_utf8(content)?)?;
+    migrate::migrate(&mut document)?;
+    let config: AppConfig = serde_yml::from_value(document)?;
+    Ok(serde_yml::to_value(config)?)
+}
+
+/// Config file read again from the recorded source.
+#[derive(Debug)]
+pub struct Pulled {
+    pub source: ConfigSource,
+    pub content: Vec<u8>,
+    /// Digest of the content.
+    pub sha256: String,
+    /// Changes of repositories and deploy targets from the current config file.
+    pub differences: Vec<Difference>,
+}
+
+impl Pulled {
+    /// Whether the remote config has changed since it was recorded.
+    pub fn is_changed(&self) -> bool {
+        self.sha256 != self.source.sha256
+    }
+}
+
+/// Read the config file again from the source recorded by `rrcm init`,
+/// verifying it in the same way. The config file is not changed.
+pub fn pull_app_config<P>(path: P, http: &HttpOptions) -> Result<Pulled>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let Some(source) = ConfigSource::read(path)? else {
+        bail!(
+            "Source of {} is not recorded. Initialize it by `rrcm init <url>`.",
+            path.display()
+        );
+    };
+    let location: Location = source.url.parse()?;
+    let content = location.read(http)?;
+    let sha256 = source
+        .verification
+        .verify(&content, |extension| {
+            location.with_extension(extension)?.read(http)
+        })
+        .with_context(|| format!("Failed to verify {}", location))?;
+
+    let format = Format::from_path(path);
+    let new = normalize_app_config(&content, format)
+        .with_context(|| format!("{} is not a valid config file.", location))?;
+    let current = normalize_app_config(&std::fs::read(path)?, format)
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+    Ok(Pulled {
+        differences: diff::diff(&current, &new),
+        source,
+        content,
+        sha256,
+    })
+}
+
+/// Write the pulled config file, backing up the current one.
+/// Returns the path of the backup.
+pub fn apply_pulled_app_config<P>(path: P, pulled: Pulled) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let backup = backup_file(path)?;
+    std::fs::write(path, &pulled.content)?;
+    ConfigSource {
+        sha256: pulled.sha256,
+        ..pulled.source
+    }
+    .write(path)?;
+    Ok(backup)
+}
+
+/// Whether the config file was edited since it was recorded.
+pub fn is_locally_modified(path: &Path, source: &ConfigSource) -> Result<bool> {
+    Ok(verify::sha256(&std::fs::read(path)?) != source.sha256)
+}
+
+/// JSON Schema of the config file.
+pub fn app_config_schema() -> Result<String> {
+    let schema = schemars::schema_for!(AppConfig);
+    Ok(serde_json::to_string_pretty(&schema)?)
+}
+
 pub fn load_app_config<P>(path: P) -> Result<AppConfig>
+where
+    P: AsRef<Path>,
+{
+    Ok(load_app_config_with_sources(path)?.0)
+}
+
+/// Load config file with the files where the repositories are defined.
+/// The local override `config.local.yaml` is merged if it exists.
+/// `dotfiles` is replaced by `RRCM_DOTFILES` if set.
+pub fn load_app_config_with_sources<P>(path: P) -> Result<(AppConfig, IndexMap<String, PathBuf>)>
 where
     P: AsRef<Path>,
 {
     let path = path.as_ref();
     ensure!(path.exists(), format!("{} does not exist.", path.display()));
-    Ok(confy::load_path(path)?)
+    let (mut config, local) = read_app_config(path)?;
+    let sources = resolve_repos(path, &mut config, local)?;
+    env::apply_dotfiles(&mut config);
+    Ok((config, sources))
+}
+
+/// Merge the repositories of the local override and included files into `config`.
+/// Returns the files where the repositories are defined.
+fn resolve_repos(
+    path: &Path,
+    config: &mut AppConfig,
+    local: Option<MergedLocal>,
+) -> Result<IndexMap<String, PathBuf>> {
+    let mut repos = IndexMap::new();
+    merge_repos(&mut repos, path, std::mem::take(&mut config.repos))?;
+    if let Some(local) = local {
+        for name in local.added {
+            if let Some((source, _)) = repos.get_mut(&name) {
+                source.clone_from(&local.path);
+            }
+        }
+    }
+    let mut visited = vec![dunce::canonicalize(path)?];
+    load_includes(path, &config.include, &mut visited, &mut repos)?;
+
+    let mut sources = IndexMap::new();
+    for (name, (source, repo)) in repos {
+        sources.insert(name, source);
+        config.repos.push(repo);
+    }
+    Ok(sources)
+}
+
+/// Add the repository to the config file, preserving comments and ordering.
+/// Repository name must be unique including included files.
+pub fn add_repository<P>(path: P, repo: &Repository) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    ensure!(
+        !repo.name.is_empty()
+            && !repo.name.contains(['/', '\\'])
+            && repo.name != "."
+            && repo.name != "..",
+        "Invalid repository name \"{}\".",
+        repo.name
+    );
+    let (_, sources) = load_app_config_with_sources(path)?;
+    if let Some(source) = sources.get(&repo.name) {
+        bail!(
+            "Repository \"{}\" is already defined in {}.",
+            repo.name,
+            source.display()
+        );
+    }
+    edit::add_repository(path, repo)
+}
+
+/// Remove the repository from the file where it is defined, preserving comments and ordering.
+/// Returns the edited file.
+pub fn remove_repository<P>(path: P, name: &str) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let (_, sources) = load_app_config_with_sources(path)?;
+    let source = sources
+        .get(name)
+        .with_context(|| format!("Repository \"{}\" is not defined.", name))?;
+    edit::remove_repository(source, name)?;
+    Ok(source.clone())
+}
+
+/// Read config file, migrating it to the current version.
+fn read_config<T>(path: &Path) -> Result<T>
+where
+    T: serde::de::DeserializeOwned,
+{
+    serde_yml::from_value(read_document(path)?).with_context(|| load_error(path))
+}
+
+/// Read config file as a document, migrating it to the current version.
+fn read_document(path: &Path) -> Result<serde_yml::Value> {
+    (|| {
+        let mut document = Format::from_path(path).parse(&std::fs::read_to_string(path)?)?;
+        let changes = migrate::migrate(&mut document)?;
+        if !changes.is_empty() {
+            log::warn!(
+                "{} is an old version config. Run `rrcm config migrate` to update.",
+                path.display()
+            );
+        }
+        Ok(document)
+    })()
+    .with_context(|| load_error(path))
+}
+
+fn load_error(path: &Path) -> String {
+    format!(
+        "Failed to load {}. Run `rrcm config validate` for details.",
+        path.display()
+    )
+}
+
+/// Local override merged into a config file.
+struct MergedLocal {
+    path: PathBuf,
+    /// Repositories added by the local override.
+    added: Vec<String>,
+}
+
+/// Read config file merged with its local override.
+fn read_app_config(path: &Path) -> Result<(AppConfig, Option<MergedLocal>)> {
+    let (document, local) = read_app_document(path)?;
+    let config = parse_app_config(path, document, local.as_ref())?;
+    Ok((config, local))
+}
+
+/// Read config file merged with its local override, as a document.
+fn read_app_document(path: &Path) -> Result<(serde_yml::Value, Option<MergedLocal>)> {
+    let mut document = read_document(path)?;
+    let Some((local_path, local)) = local::read(path)? else {
+        return Ok((document, None));
+    };
+    let added = local::merge(&mut document, local)
+        .with_context(|| format!("Failed to merge {}", local_path.display()))?;
+    Ok((
+        document,
+        Some(MergedLocal {
+            path: local_path,
+            added,
+        }),
+    ))
+}
+
+fn parse_app_config(
+    path: &Path,
+    document: serde_yml::Value,
+    local: Option<&MergedLocal>,
+) -> Result<AppConfig> {
+    match local {
+        Some(local) => serde_yml::from_value(document).with_context(|| {
+            format!(
+                "Failed to load {} merged with {}",
+                path.display(),
+                local.path.display()
+            )
+        }),
+        None => serde_yml::from_value(document).with_context(|| load_error(path)),
+    }
+}
+
+/// Migrate config file to the current version.
+/// The original file is backed up before it is rewritten.
+/// `version` is updated in place, keeping comments and formatting,
+/// unless a migration changes the structure or the file is JSON.
+pub fn migrate_app_config<P>(path: P) -> Result<Migrated>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    ensure!(path.exists(), format!("{} does not exist.", path.display()));
+
+    let format = Format::from_path(path);
+    let text = std::fs::read_to_string(path)?;
+    let mut document = format.parse(&text)?;
+    let version = migrate::version(&document)?;
+    let changes = migrate::migrate(&mut document)?;
+    if changes.is_empty() {
+        return Ok(Migrated {
+            changes,
+            rewritten: false,
+        });
+    }
+    // check the migrated document before rewriting
+    serde_yml::from_value::<AppConfig>(document.clone()).context("Migrated config is invalid.")?;
+
+    let edited = (!migrate::requires_rewrite(version))
+        .then(|| edit::set_version(format, &text, CURRENT_VERSION).ok())
+        .flatten()
+        .filter(|edited| format.parse(edited).ok().as_ref() == Some(&document));
+    let rewritten = edited.is_none();
+    let text = match edited {
+        Some(edited) => edited,
+        None => format.serialize(&document)?,
+    };
+    backup_file(path)?;
+    std::fs::write(path, text)?;
+    Ok(Migrated { changes, rewritten })
+}
+
+/// Result of [`migrate_app_config`].
+#[derive(Debug)]
+pub struct Migrated {
+    /// Changes made, empty if the file is already the current version.
+    pub changes: Vec<String>,
+    /// The file is rewritten as a whole, and comments and formatting are lost.
+    pub rewritten: bool,
+}
+
+/// Find config file in the directory.
+/// The first existing file of [`CONFIG_FILE_NAMES`] is returned.
+/// If none exists, `config.yaml` is returned.
+pub fn find_app_config<P>(dir: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let dir = dir.as_ref();
+    let mut found = CONFIG_FILE_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .filter(|path| path.exists());
+    let Some(path) = found.next() else {
+        return dir.join(CONFIG_FILE_NAMES[0]);
+    };
+    for ignored in found {
+        log::warn!(
+            "{} is ignored because {} exists.",
+            ignored.display(),
+            path.display()
+        );
+    }
+    path
+}
+
+/// Result of [`convert_app_config`].
+#[derive(Debug)]
+pub struct Converted {
+    pub path: PathBuf,
+    pub backup: PathBuf,
+    /// Paths of null values, which can not be represented in the format.
+    pub removed: Vec<String>,
+}
+
+/// Convert config file to the format.
+/// The converted file is written next to the original with the extension of the format,
+/// and the original file is moved to `<file>.bak`.
+/// Null values, e.g. `~` of host rules, which the format can not represent, are an error
+/// since removing them changes the deployment, unless `drop_nulls` is set.
+pub fn convert_app_config<P>(path: P, to: Format, drop_nulls: bool) -> Result<Converted>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    ensure!(path.exists(), format!("{} does not exist.", path.display()));
+    let from = Format::from_path(path);
+    ensure!(from != to, "{} is already {}.", path.display(), to);
+    // `config pull` compares the digest and parses the download in the format of the local file
+    ensure!(
+        !ConfigSource::path(path).exists(),
+        "{} was downloaded from the source recorded in {}, and `config pull` reads it as {}. \
+         Convert the source instead, or remove the record to keep the file local.",
+        path.display(),
+        ConfigSource::path(path).display(),
+        from
+    );
+
+    let converted = path.with_extension(to.extension());
+    ensure!(
+        !converted.exists(),
+        format!("{} already exists.", converted.display())
+    );
+
+    let document = from.parse(&std::fs::read_to_string(path)?)?;
+    let (text, removed) = to.serialize_document(&document)?;
+    ensure!(
+        removed.is_empty() || drop_nulls,
+        "{} can not be represented in {} since null. Use --drop-nulls to remove them.",
+        removed.join(", "),
+        to
+    );
+    // check the converted document before writing
+    to.deserialize::<serde_yml::Value>(&text)
+        .context("Converted config is invalid.")?;
+    std::fs::write(&converted, text)?;
+    let backup = backup_file(path)?;
+    std::fs::remove_file(path)?;
+
+    Ok(Converted {
+        path: converted,
+        backup,
+        removed,
+    })
+}
+
+/// Path of the backup of the file, `<file>.bak`.
+pub fn backup_path<P>(path: P) -> PathBuf
+where
+    P: AsRef<Path>,
+{
+    let mut backup = path.as_ref().as_os_str().to_owned();
+    backup.push(".bak");
+    PathBuf::from(backup)
+}
+
+/// Copy the file to `<file>.bak`. An existing backup is overwritten.
+pub(crate) fn backup_file(path: &Path) -> Result<PathBuf> {
+    let backup = backup_path(path);
+    std::fs::copy(path, &backup).with_context(|| {
+        format!(
+            "Failed to back up {} to {}",
+            path.display(),
+            backup.display()
+        )
+    })?;
+    Ok(backup)
+}
+
+/// Resolve path of an included config file.
+/// Environment variables are expanded and
+/// relative paths are resolved from the directory of the including file.
+pub(crate) fn resolve_include_path(including: &Path, include: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(
+        expand_env_var(include).with_context(|| format!("Failed to expand include {}", include))?,
+    );
+    if path.is_absolute() {
+        return Ok(path);
+    }
+    Ok(including
+        .parent()
+        .map(|parent| parent.join(&path))
+        .unwrap_or(path))
+}
+
+/// Load included config files recursively and merge their repositories.
+/// Files already loaded are skipped, so include cycles are ignored.
+fn load_includes(
+    including: &Path,
+    includes: &[String],
+    visited: &mut Vec<PathBuf>,
+    repos: &mut IndexMap<String, (PathBuf, Repository)>,
+) -> Result<()> {
+    for include in includes {
+        let path = resolve_include_path(including, include)?;
+        ensure!(
+            path.exists(),
+            "{} included from {} does not exist.",
+            path.display(),
+            including.display()
+        );
+        let canonical = dunce::canonicalize(&path)?;
+        if visited.contains(&canonical) {
+            log::debug!("Skip include {} already loaded", path.display());
+            continue;
+        }
+        visited.push(canonical);
+
+        let included: IncludedConfig = read_config(&path).with_context(|| {
+            format!(
+                "Failed to load {} included from {}",
+                path.display(),
+                including.display()
+            )
+        })?;
+        merge_repos(repos, &path, included.repos)?;
+        load_includes(&path, &included.include, visited, repos)?;
+    }
+    Ok(())
+}
+
+/// Merge repositories defined in the file.
+/// Repository names must be unique across all files.
+fn merge_repos(
+    repos: &mut IndexMap<String, (PathBuf, Repository)>,
+    path: &Path,
+    new_repos: Vec<Repository>,
+) -> Result<()> {
+    for repo in new_repos {
+        match repos.entry(repo.name.clone()) {
+            Entry::Occupied(entry) => {
+                bail!(
+                    "Repository \"{}\" defined in {} is already defined in {}.",
+                    repo.name,
+                    path.display(),
+                    entry.get().0.display()
+                );
+            }
+            Entry::Vacant(entry) => {
+                entry.insert((path.to_path_buf(), repo));
+            }
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use indexmap::indexmap;
+    use rstest::rstest;
+
+    fn os_path(path: &str) -> OsPath {
+        OsPath {
+            windows: Some(path.to_string()),
+            mac: Some(path.to_string()),
+            linux: Some(path.to_string()),
+            ..OsPath::default()
+        }
+    }
 
     #[test]
     fn test_app_config() {
@@ -137,12 +1111,161 @@ mod tests {
         assert_eq!(config.repos.len(), 0);
     }
 
+    #[test]
+    fn test_migrate_app_config() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.yaml");
+        let original = "# comment\ndotfiles:\n  linux: /dotfiles\nrepos: []\n";
+        std::fs::write(&config, original)?;
+
+        // old version config can be loaded
+        assert_eq!(load_app_config(&config)?.version, CURRENT_VERSION);
+
+        let migrated = migrate_app_config(&config)?;
+        assert_eq!(migrated.changes.len(), 1);
+        assert!(!migrated.rewritten);
+        assert_eq!(std::fs::read_to_string(backup_path(&config))?, original);
+        // comments are kept
+        assert_eq!(
+            std::fs::read_to_string(&config)?,
+            format!(
+                "# comment\nversion: {}\ndotfiles:\n  linux: /dotfiles\nrepos: []\n",
+                CURRENT_VERSION
+            )
+        );
+
+        // already migrated
+        std::fs::remove_file(backup_path(&config))?;
+        assert!(migrate_app_config(&config)?.changes.is_empty());
+        assert!(!backup_path(&config).exists());
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("config.yaml")]
+    #[case("config.toml")]
+    #[case("config.json")]
+    fn test_init_app_config(#[case] name: &str) -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join(name);
+        init_app_config(&config)?;
+
+        let loaded = load_app_config(&config)?;
+        assert_eq!(loaded.version, CURRENT_VERSION);
+        assert_eq!(loaded.dotfiles.linux, AppConfig::default().dotfiles.linux);
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_app_config_toml() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.toml");
+        std::fs::write(
+            &config,
+            indoc::indoc! {r#"
+                version = 1
+                include = ["shared.json"]
+
+                [dotfiles]
+                default = "/dotfiles"
+
+                [[repos]]
+                name = "example1"
+                url = "https://example.com/example1.git"
+
+                [repos.deploy.home]
+                default = "/home/example"
+            "#},
+        )?;
+        std::fs::write(
+            temp.path().join("shared.json"),
+            r#"{"repos": [{"name": "example2", "url": "https://example.com/example2.git", "deploy": {}}]}"#,
+        )?;
+
+        let loaded = load_app_config(&config)?;
+        assert_eq!(
+            loaded
+                .repos
+                .iter()
+                .map(|repo| &repo.name)
+                .collect::<Vec<_>>(),
+            vec!["example1", "example2"]
+        );
+        assert_eq!(
+            loaded.repos[0].deploy["home"].path.default,
+            Some("/home/example".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_find_app_config() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        assert_eq!(
+            find_app_config(temp.path()),
+            temp.path().join("config.yaml")
+        );
+
+        std::fs::write(temp.path().join("config.json"), "{}")?;
+        assert_eq!(
+            find_app_config(temp.path()),
+            temp.path().join("config.json")
+        );
+
+        std::fs::write(temp.path().join("config.toml"), "")?;
+        assert_eq!(
+            find_app_config(temp.path()),
+            temp.path().join("config.toml")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_app_config() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.yaml");
+        let original = "version: 1\ndotfiles:\n  windows: ~\n  linux: /dotfiles\nrepos: []\n";
+        std::fs::write(&config, original)?;
+
+        // null values are not removed without drop_nulls
+        assert!(convert_app_config(&config, Format::Toml, false).is_err());
+        assert_eq!(std::fs::read_to_string(&config)?, original);
+        assert!(!temp.path().join("config.toml").exists());
+
+        let converted = convert_app_config(&config, Format::Toml, true)?;
+        assert_eq!(converted.path, temp.path().join("config.toml"));
+        assert_eq!(converted.removed, vec!["dotfiles.windows"]);
+        assert!(!config.exists());
+        assert_eq!(std::fs::read_to_string(&converted.backup)?, original);
+        assert_eq!(
+            load_app_config(&converted.path)?.dotfiles.linux,
+            Some("/dotfiles".to_string())
+        );
+
+        // same format
+        assert!(convert_app_config(&converted.path, Format::Toml, false).is_err());
+
+        // downloaded file can not be converted, since `config pull` would overwrite it
+        std::fs::write(temp.path().join("config.source.yaml"), "")?;
+        assert!(convert_app_config(&converted.path, Format::Json, false).is_err());
+        std::fs::remove_file(temp.path().join("config.source.yaml"))?;
+
+        let converted = convert_app_config(&converted.path, Format::Json, false)?;
+        assert_eq!(converted.path, temp.path().join("config.json"));
+        assert_eq!(
+            load_app_config(&converted.path)?.dotfiles.linux,
+            Some("/dotfiles".to_string())
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_os_path() {
         let os_path = OsPath {
             windows: Some("%USERPROFILE%\\dotfiles".to_string()),
             mac: Some("${HOME}/.dotfiles".to_string()),
             linux: Some("${HOME}/.dotfiles".to_string()),
+            ..OsPath::default()
         };
         assert_eq!(
             os_path.to_pathbuf().unwrap(),
@@ -153,4 +1276,428 @@ mod tests {
             }
         );
     }
+
+    #[test]
+    fn test_resolve_deploy() {
+        let repo = Repository {
+            name: "example".to_string(),
+            url: Some("https://example.com/example.git".to_string()),
+            deploy: indexmap! {
+                "home".to_string() => os_path("home").into(),
+                "config".to_string() => os_path("config").into(),
+            },
+            hosts: indexmap! {
+                "build*".to_string() => indexmap! {
+                    "home".to_string() => Some(os_path("build-home").into()),
+                    "build".to_string() => Some(os_path("build").into()),
+                },
+                "build02".to_string() => indexmap! {
+                    "home".to_string() => Some(os_path("build02-home").into()),
+                    "config".to_string() => None,
+                },
+            },
+            ..Repository::default()
+        };
+
+        let deploy = repo.resolve_deploy("laptop");
+        assert_eq!(deploy.keys().collect::<Vec<_>>(), vec![&"home", &"config"]);
+        assert_eq!(deploy["home"].path.linux, Some("home".to_string()));
+        assert!(repo.host_rules("laptop").is_empty());
+
+        let deploy = repo.resolve_deploy("build01");
+        assert_eq!(
+            deploy.keys().collect::<Vec<_>>(),
+            vec![&"home", &"config", &"build"]
+        );
+        assert_eq!(deploy["home"].path.linux, Some("build-home".to_string()));
+        assert_eq!(repo.host_rules("build01"), vec!["build*"]);
+
+        let deploy = repo.resolve_deploy("build02");
+        assert_eq!(deploy.keys().collect::<Vec<_>>(), vec![&"home", &"build"]);
+        assert_eq!(deploy["home"].path.linux, Some("build02-home".to_string()));
+        assert_eq!(repo.host_rules("build02"), vec!["build*", "build02"]);
+    }
+
+    fn write_repos(path: &Path, include: &[&str], repos: &[&str]) {
+        let include = if include.is_empty() {
+            String::new()
+        } else {
+            include
+                .iter()
+                .map(|include| format!("  - '{}'\n", include))
+                .fold("include:\n".to_string(), |acc, line| acc + &line)
+        };
+        let repos = repos
+            .iter()
+            .map(|name| {
+                format!(
+                    "  - name: {}\n    url: 'https://example.com/{}.git'\n    deploy: {{}}\n",
+                    name, name
+                )
+            })
+            .collect::<String>();
+        std::fs::write(
+            path,
+            format!(
+                "dotfiles:\n  linux: /dotfiles\n{}repos:\n{}",
+                include, repos
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_load_app_config_include() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        std::fs::create_dir(temp.path().join("team"))?;
+        let tools = temp.path().join("team").join("tools.yaml");
+
+        let config = temp.path().join("config.yaml");
+        write_repos(
+            &config,
+            &["team/shared.yaml", &tools.to_string_lossy()],
+            &["personal"],
+        );
+        // relative to team/shared.yaml, and already loaded
+        write_repos(
+            &temp.path().join("team").join("shared.yaml"),
+            &["tools.yaml"],
+            &["shared1", "shared2"],
+        );
+        write_repos(
+            &temp.path().join("team").join("tools.yaml"),
+            &["../config.yaml"],
+            &["tools"],
+        );
+
+        let config = load_app_config(&config)?;
+        assert_eq!(
+            config
+                .repos
+                .iter()
+                .map(|repo| repo.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["personal", "shared1", "shared2", "tools"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_app_config_include_duplicate() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.yaml");
+        let shared = temp.path().join("shared.yaml");
+        write_repos(&config, &["shared.yaml"], &["dotfiles"]);
+        write_repos(&shared, &[], &["dotfiles"]);
+
+        let err = load_app_config(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Repository \"dotfiles\" defined in {} is already defined in {}.",
+                shared.display(),
+                config.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_app_config_include_not_found() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.yaml");
+        write_repos(&config, &["missing.yaml"], &[]);
+
+        let err = load_app_config(&config).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{} included from {} does not exist.",
+                temp.path().join("missing.yaml").display(),
+                config.display()
+            )
+        );
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(None, None, None, Some(None))]
+    #[case(Some("main"), None, None, Some(Some(Pin::Branch("main"))))]
+    #[case(None, Some("v1.0"), None, Some(Some(Pin::Tag("v1.0"))))]
+    #[case(None, None, Some("0123abc"), Some(Some(Pin::Rev("0123abc"))))]
+    #[case(Some("main"), Some("v1.0"), None, None)]
+    fn test_repository_pin(
+        #[case] branch: Option<&str>,
+        #[case] tag: Option<&str>,
+        #[case] rev: Option<&str>,
+        #[case] expected: Option<Option<Pin>>,
+    ) {
+        let repo = Repository {
+            name: "example".to_string(),
+            branch: branch.map(str::to_string),
+            tag: tag.map(str::to_string),
+            rev: rev.map(str::to_string),
+            ..Repository::default()
+        };
+        // None if error
+        assert_eq!(repo.pin().ok(), expected);
+    }
+
+    #[rstest]
+    #[case(
+        Some("https://example.com/example.git"),
+        None,
+        Some(Source::Url("https://example.com/example.git"))
+    )]
+    #[case(None, Some("local"), Some(Source::Path("local")))]
+    #[case(Some("https://example.com/example.git"), Some("local"), None)]
+    #[case(None, None, None)]
+    fn test_repository_source(
+        #[case] url: Option<&str>,
+        #[case] path: Option<&str>,
+        #[case] expected: Option<Source>,
+    ) {
+        let repo = Repository {
+            name: "example".to_string(),
+            url: url.map(str::to_string),
+            path: path.map(str::to_string),
+            ..Repository::default()
+        };
+        // None if error
+        assert_eq!(repo.source().ok(), expected);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[rstest]
+    #[case(None, None, "/dotfiles/example")]
+    #[case(Some("local/example"), None, "/dotfiles/local/example")]
+    #[case(Some("${LOCAL}/example"), None, "/local/example")]
+    #[case(None, Some("/secure"), "/secure/example")]
+    #[case(Some("local"), Some("/secure"), "/secure/local")]
+    fn test_repository_to_pathbuf_with(
+        #[case] path: Option<&str>,
+        #[case] dir: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        let repo = Repository {
+            name: "example".to_string(),
+            path: path.map(str::to_string),
+            dir: dir.map(|dir| OsPath {
+                default: Some(dir.to_string()),
+                ..OsPath::default()
+            }),
+            ..Repository::default()
+        };
+        let vars = indexmap! {"LOCAL".to_string() => "/local".to_string()};
+        assert_eq!(
+            repo.to_pathbuf_with(Path::new("/dotfiles"), &vars).unwrap(),
+            PathBuf::from(expected)
+        );
+    }
+
+    #[test]
+    fn test_repository_dir_undefined() {
+        // dir not defined for the running OS falls back to dotfiles
+        let repo = Repository {
+            name: "example".to_string(),
+            dir: Some(OsPath::default()),
+            ..Repository::default()
+        };
+        assert_eq!(
+            repo.to_pathbuf_with(Path::new("dotfiles"), &IndexMap::new())
+                .unwrap(),
+            Path::new("dotfiles").join("example")
+        );
+    }
+
+    #[test]
+    fn test_repository_pin_local() {
+        let repo = Repository {
+            name: "example".to_string(),
+            path: Some("local".to_string()),
+            branch: Some("main".to_string()),
+            ..Repository::default()
+        };
+        assert_eq!(
+            repo.pin().unwrap_err().to_string(),
+            "Local repository \"example\" cannot be pinned to branch main."
+        );
+    }
+
+    #[test]
+    fn test_add_remove_repository() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.yaml");
+        let shared = temp.path().join("shared.yaml");
+        write_repos(&config, &["shared.yaml"], &["personal"]);
+        write_repos(&shared, &[], &["shared"]);
+
+        let repo = |name: &str| Repository {
+            name: name.to_string(),
+            url: Some(format!("https://example.com/{}.git", name)),
+            ..Repository::default()
+        };
+        add_repository(&config, &repo("added"))?;
+        assert!(add_repository(&config, &repo("shared")).is_err());
+        assert!(add_repository(&config, &repo("../added")).is_err());
+        assert_eq!(
+            load_app_config(&config)?
+                .repos
+                .iter()
+                .map(|repo| repo.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["personal", "added", "shared"]
+        );
+
+        // removed from the file where it is defined
+        assert_eq!(remove_repository(&config, "shared")?, shared);
+        assert_eq!(remove_repository(&config, "added")?, config);
+        assert!(remove_repository(&config, "added").is_err());
+        assert_eq!(
+            load_app_config(&config)?
+                .repos
+                .iter()
+                .map(|repo| repo.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["personal"]
+        );
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(&["linux", "unix", "default"], Some("linux"))]
+    #[case(&["unix", "default"], Some("unix"))]
+    #[case(&["default"], Some("default"))]
+    #[case(&["mac", "unix", "default"], Some("unix"))]
+    #[case(&["windows", "default"], Some("default"))]
+    #[case(&["freebsd", "unix", "default"], Some("unix"))]
+    #[case(&["openbsd"], None)]
+    #[case(&["wsl", "linux", "unix", "default"], Some("wsl"))]
+    fn test_os_path_lookup(#[case] keys: &[&str], #[case] expected: Option<&str>) {
+        let os_path = OsPath {
+            linux: Some("linux".to_string()),
+            wsl: Some("wsl".to_string()),
+            unix: Some("unix".to_string()),
+            default: Some("default".to_string()),
+            ..OsPath::default()
+        };
+        assert_eq!(os_path.lookup(keys).map(String::as_str), expected);
+    }
+
+    #[test]
+    fn test_os_keys() {
+        assert_eq!(os_keys(false), OS_KEYS);
+        if cfg!(target_os = "linux") {
+            assert_eq!(os_keys(true), WSL_KEYS);
+        } else {
+            assert_eq!(os_keys(true), OS_KEYS);
+        }
+    }
+
+    #[test]
+    fn test_os_path_not_defined() {
+        let os_path = OsPath::default();
+        assert_eq!(
+            os_path.to_pathbuf().unwrap_err().to_string(),
+            format!("{} Path not defined.", OS_NAME)
+        );
+    }
+
+    #[test]
+    fn test_os_path_unix_default() {
+        let os_path: OsPath = serde_yml::from_str(indoc::indoc! {"
+            windows: C:\\
+            unix: /
+        "})
+        .unwrap();
+        assert_eq!(
+            os_path.to_pathbuf().unwrap(),
+            PathBuf::from(if cfg!(target_os = "windows") {
+                "C:\\"
+            } else {
+                "/"
+            })
+        );
+
+        let os_path: OsPath = serde_yml::from_str("default: /").unwrap();
+        assert_eq!(os_path.to_pathbuf().unwrap(), PathBuf::from("/"));
+    }
+
+    #[test]
+    fn test_app_config_schema() -> Result<()> {
+        let schema: serde_json::Value = serde_json::from_str(&app_config_schema()?)?;
+        assert_eq!(schema["title"], "AppConfig");
+        assert_eq!(schema["required"], serde_json::json!(["dotfiles", "repos"]));
+        assert_eq!(
+            schema["properties"]["dotfiles"]["description"],
+            "Directory where repositories are downloaded."
+        );
+        assert!(schema["$defs"]["OsPath"]["description"]
+            .as_str()
+            .unwrap()
+            .contains("`${NAME}`"));
+        assert_eq!(
+            schema["$defs"]["Repository"]["properties"]["url"]["description"],
+            "Git repository url."
+        );
+        Ok(())
+    }
+
+    fn app_config_with_vars(vars: &[(&str, &str)]) -> AppConfig {
+        AppConfig {
+            vars: vars
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            ..AppConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_resolve_vars() -> Result<()> {
+        let config = app_config_with_vars(if cfg!(target_os = "windows") {
+            &[
+                ("PROJECTS", "%WORK_ROOT%\\projects"),
+                ("WORK_ROOT", "%USERPROFILE%\\work"),
+            ]
+        } else {
+            &[
+                ("PROJECTS", "${WORK_ROOT}/projects"),
+                ("WORK_ROOT", "${HOME}/work"),
+            ]
+        });
+        let home = dirs::home_dir().unwrap().to_string_lossy().to_string();
+        let sep = std::path::MAIN_SEPARATOR;
+
+        let vars = config.resolve_vars()?;
+        assert_eq!(vars["WORK_ROOT"], format!("{home}{sep}work"));
+        assert_eq!(vars["PROJECTS"], format!("{home}{sep}work{sep}projects"));
+
+        let os_path = OsPath {
+            windows: Some("%PROJECTS%\\rrcm".to_string()),
+            mac: Some("${PROJECTS}/rrcm".to_string()),
+            linux: Some("${PROJECTS}/rrcm".to_string()),
+            ..OsPath::default()
+        };
+        assert_eq!(
+            os_path.to_pathbuf_with(&vars)?,
+            PathBuf::from(format!("{home}{sep}work{sep}projects{sep}rrcm"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_vars_cycle() {
+        let config = app_config_with_vars(if cfg!(target_os = "windows") {
+            &[("A", "%B%"), ("B", "%C%"), ("C", "%A%")]
+        } else {
+            &[("A", "${B}"), ("B", "${C}"), ("C", "${A}")]
+        });
+        let err = config.resolve_vars().unwrap_err();
+        assert_eq!(
+            format!("{:#}", err),
+            "Failed to expand variable A: Failed to expand variable B: \
+             Failed to expand variable C: Variable cycle detected: A -> B -> C -> A"
+        );
+    }
 }
@@ -0,0 +1,161 @@
+//! Structured diff of config files.
+//!
+//! Repositories are matched by name, and deploy targets and host rules by key.
+//! Other values are compared as a whole.
+use serde_yml::{Mapping, Value};
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// A changed item, e.g. `repos.example1.deploy.home`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    pub change: Change,
+    pub key: String,
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = match self.change {
+            Change::Added => "+",
+            Change::Removed => "-",
+            Change::Changed => "~",
+        };
+        write!(f, "{} {}", sign, self.key)
+    }
+}
+
+/// Differences between the documents, in the order of keys.
+pub fn diff(old: &Value, new: &Value) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    diff_mapping("", mapping(old), mapping(new), 0, &mut differences);
+    differences
+}
+
+fn mapping(value: &Value) -> Mapping {
+    value.as_mapping().cloned().unwrap_or_default()
+}
+
+/// Repositories keyed by name.
+fn repos(value: &Value) -> Mapping {
+    value
+        .as_sequence()
+        .into_iter()
+        .flatten()
+        .filter_map(|repo| Some((repo.get("name")?.clone(), repo.clone())))
+        .collect()
+}
+
+/// `depth` is 0 for the document, 1 for repositories, 2 for a repository
+/// and 3 for deploy targets or host rules.
+fn diff_mapping(path: &str, old: Mapping, new: Mapping, depth: usize, out: &mut Vec<Difference>) {
+    let mut keys = old.keys().cloned().collect::<Vec<_>>();
+    keys.extend(new.keys().filter(|key| !old.contains_key(*key)).cloned());
+
+    for key in keys {
+        let name = key
+            .as_str()
+            .map(str::to_string)
+            .unwrap_or_else(|| serde_yml::to_string(&key).unwrap_or_default());
+        let key_path = if path.is_empty() {
+            name.clone()
+        } else {
+            format!("{}.{}", path, name)
+        };
+        let change = match (old.get(&key), new.get(&key)) {
+            (Some(old), Some(new)) if old == new => continue,
+            (Some(old), Some(new)) => match (depth, name.as_str()) {
+                (0, "repos") => {
+                    diff_mapping(&key_path, repos(old), repos(new), 1, out);
+                    continue;
+                }
+                (1, _) => {
+                    diff_mapping(&key_path, mapping(old), mapping(new), 2, out);
+                    continue;
+                }
+                (2, "deploy" | "hosts") => {
+                    diff_mapping(&key_path, mapping(old), mapping(new), 3, out);
+                    continue;
+                }
+                _ => Change::Changed,
+            },
+            (Some(_), None) => Change::Removed,
+            (None, Some(_)) => Change::Added,
+            (None, None) => continue,
+        };
+        out.push(Difference {
+            change,
+            key: key_path,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_diff() {
+        let old: Value = serde_yml::from_str(indoc! {"
+            version: 1
+            dotfiles:
+              default: /dotfiles
+            repos:
+              - name: example1
+                url: https://example.com/example1.git
+                deploy:
+                  home:
+                    default: /home
+                  config:
+                    default: /config
+              - name: example2
+                url: https://example.com/example2.git
+                deploy: {}
+        "})
+        .unwrap();
+        let new: Value = serde_yml::from_str(indoc! {"
+            version: 1
+            dotfiles:
+              default: /dotfiles
+            vars:
+              A: a
+            repos:
+              - name: example1
+                url: https://example.com/example1.git
+                branch: main
+                deploy:
+                  home:
+                    default: /home2
+                  ssh:
+                    default: /ssh
+              - name: example3
+                url: https://example.com/example3.git
+                deploy: {}
+        "})
+        .unwrap();
+
+        assert_eq!(
+            diff(&old, &new)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            vec![
+                "~ repos.example1.deploy.home",
+                "- repos.example1.deploy.config",
+                "+ repos.example1.deploy.ssh",
+                "+ repos.example1.branch",
+                "- repos.example2",
+                "+ repos.example3",
+                "+ vars",
+            ]
+        );
+        assert_eq!(diff(&old, &old), vec![]);
+    }
+}
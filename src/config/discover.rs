@@ -0,0 +1,362 @@
+//! Discovery of config files.
+//!
+//! Without `--config` or `RRCM_CONFIG`, config files are loaded in the following order.
+//! Later files take precedence over earlier ones.
+//! 1. system: `rrcm/config.yaml` in `$XDG_CONFIG_DIRS` (`/etc/xdg` if not set, except on Windows and macOS).
+//!    The first directory takes precedence.
+//! 2. user: `rrcm/config.yaml` in the config directory.
+//! 3. project: `.rrcm.yaml` in the current directory or the nearest parent directory.
+//!
+//! Every key is optional in these files, and only the keys set override the lower layers.
+//! `vars` are merged, `dotfiles` and `relative_links` are replaced, and repositories with the same name are replaced.
+//! The file of the highest precedence is the one edited by commands such as `rrcm repo add`.
+use super::env::{self, CONFIG_ENV};
+use super::{
+    find_app_config, load_app_config_with_sources, parse_app_config, read_app_document,
+    resolve_repos, AppConfig,
+};
+use anyhow::{ensure, Context as _, Result};
+use indexmap::IndexMap;
+use serde_yml::Value;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// File names of the project config file, in priority order.
+pub const PROJECT_CONFIG_FILE_NAMES: [&str; 3] = [".rrcm.yaml", ".rrcm.toml", ".rrcm.json"];
+
+/// Where the config file comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    System,
+    User,
+    Project,
+    /// Given by `--config`.
+    Flag,
+    /// Given by `RRCM_CONFIG`.
+    Env,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::System => write!(f, "system"),
+            Self::User => write!(f, "user"),
+            Self::Project => write!(f, "project"),
+            Self::Flag => write!(f, "--config"),
+            Self::Env => write!(f, "{}", CONFIG_ENV),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub layer: Layer,
+}
+
+/// Config files in order of loading. Never empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigFiles {
+    pub files: Vec<ConfigFile>,
+}
+
+impl ConfigFiles {
+    /// The config file of the highest precedence.
+    pub fn primary(&self) -> &Path {
+        &self.files[self.files.len() - 1].path
+    }
+
+    pub fn load(&self) -> Result<AppConfig> {
+        Ok(self.load_with_sources()?.0)
+    }
+
+    /// Load and merge the config files, with the files where the repositories are defined.
+    /// A file given by `--config` or `RRCM_CONFIG` is loaded as a complete config file.
+    pub fn load_with_sources(&self) -> Result<(AppConfig, IndexMap<String, PathBuf>)> {
+        if let [file] = self.files.as_slice() {
+            if matches!(file.layer, Layer::Flag | Layer::Env) {
+                return load_app_config_with_sources(&file.path);
+            }
+        }
+
+        let mut config = AppConfig::default();
+        let mut sources = IndexMap::new();
+        for file in &self.files {
+            let (upper, upper_sources, keys) = load_layer(&file.path)?;
+            let has = |key: &str| keys.iter().any(|k| k == key);
+            if has("version") {
+                config.version = upper.version;
+            }
+            config.vars.extend(upper.vars);
+            if has("dotfiles") {
+                config.dotfiles = upper.dotfiles;
+            }
+            if has("relative_links") {
+                config.relative_links = upper.relative_links;
+            }
+            for repo in upper.repos {
+                if let Some(source) = upper_sources.get(&repo.name) {
+                    sources.insert(repo.name.clone(), source.clone());
+                }
+                match config.repos.iter_mut().find(|r| r.name == repo.name) {
+                    Some(existing) => {
+                        log::info!(
+                            "Repository \"{}\" is overridden by {}",
+                            repo.name,
+                            file.path.display()
+                        );
+                        *existing = repo;
+                    }
+                    None => config.repos.push(repo),
+                }
+            }
+        }
+        env::apply_dotfiles(&mut config);
+        Ok((config, sources))
+    }
+}
+
+/// Load the config file of a layer, where every key is optional.
+/// Returns the top level keys set in the file or its local override.
+fn load_layer(path: &Path) -> Result<(AppConfig, IndexMap<String, PathBuf>, Vec<String>)> {
+    ensure!(path.exists(), format!("{} does not exist.", path.display()));
+    let (mut document, local) = read_app_document(path)?;
+    let mut keys = Vec::new();
+    if let Some(mapping) = document.as_mapping_mut() {
+        keys.extend(mapping.keys().filter_map(Value::as_str).map(str::to_string));
+        // required keys of a complete config file
+        for (key, default) in [
+            ("dotfiles", Value::Mapping(Default::default())),
+            ("repos", Value::Sequence(Vec::new())),
+        ] {
+            mapping.entry(Value::from(key)).or_insert(default);
+        }
+    }
+    let mut config = parse_app_config(path, document, local.as_ref())?;
+    let sources = resolve_repos(path, &mut config, local)?;
+    Ok((config, sources, keys))
+}
+
+/// Config files to load. Only the file given by `path` or `RRCM_CONFIG` if any.
+pub fn discover_app_config(path: Option<&Path>) -> Result<ConfigFiles> {
+    if let Some(path) = path {
+        return Ok(single(path.to_path_buf(), Layer::Flag));
+    }
+    if let Some(path) = env::var(CONFIG_ENV) {
+        return Ok(single(PathBuf::from(path), Layer::Env));
+    }
+    let user = dirs::config_dir()
+        .context("config directory not found")?
+        .join("rrcm");
+    Ok(discover(&std::env::current_dir()?, &user, &system_dirs()))
+}
+
+/// Config file path. The file of the highest precedence of [`discover_app_config`].
+pub fn app_config_path(path: Option<&Path>) -> Result<PathBuf> {
+    Ok(discover_app_config(path)?.primary().to_path_buf())
+}
+
+fn single(path: PathBuf, layer: Layer) -> ConfigFiles {
+    ConfigFiles {
+        files: vec![ConfigFile { path, layer }],
+    }
+}
+
+/// `system` are in priority order. If no file exists, the user config file is returned.
+fn discover(cwd: &Path, user: &Path, system: &[PathBuf]) -> ConfigFiles {
+    let mut files = system
+        .iter()
+        .rev()
+        .map(|dir| ConfigFile {
+            path: find_app_config(dir.join("rrcm")),
+            layer: Layer::System,
+        })
+        .collect::<Vec<_>>();
+    files.push(ConfigFile {
+        path: find_app_config(user),
+        layer: Layer::User,
+    });
+    if let Some(path) = find_project_config(cwd) {
+        files.push(ConfigFile {
+            path,
+            layer: Layer::Project,
+        });
+    }
+    files.retain(|file| file.path.exists());
+    if files.is_empty() {
+        return single(find_app_config(user), Layer::User);
+    }
+    ConfigFiles { files }
+}
+
+fn find_project_config(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors().find_map(|dir| {
+        PROJECT_CONFIG_FILE_NAMES
+            .iter()
+            .map(|name| dir.join(name))
+            .find(|path| path.is_file())
+    })
+}
+
+fn system_dirs() -> Vec<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_DIRS").filter(|dirs| !dirs.is_empty()) {
+        Some(dirs) => std::env::split_paths(&dirs)
+            .filter(|dir| dir.is_absolute())
+            .collect(),
+        None if cfg!(any(target_os = "windows", target_os = "macos")) => Vec::new(),
+        None => vec![PathBuf::from("/etc/xdg")],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use pretty_assertions::assert_eq;
+
+    fn write_config(path: &Path, dotfiles: &str, repos: &[(&str, &str)]) -> Result<()> {
+        let repos = repos
+            .iter()
+            .map(|(name, url)| format!("  - name: {}\n    url: {}\n    deploy: {{}}\n", name, url))
+            .collect::<String>();
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(
+            path,
+            format!(
+                "version: 1\ndotfiles:\n  default: {}\nrepos:\n{}",
+                dotfiles, repos
+            ),
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_discover() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let user = temp.path().join("user/rrcm");
+        let system = vec![temp.path().join("xdg1"), temp.path().join("xdg2")];
+        let cwd = temp.child("project/sub");
+        cwd.create_dir_all()?;
+
+        // nothing exists
+        let files = discover(cwd.path(), &user, &system);
+        assert_eq!(files, single(user.join("config.yaml"), Layer::User));
+
+        write_config(&system[0].join("rrcm/config.yaml"), "/xdg1", &[])?;
+        write_config(&system[1].join("rrcm/config.yaml"), "/xdg2", &[])?;
+        write_config(&user.join("config.yaml"), "/user", &[])?;
+        write_config(&temp.path().join("project/.rrcm.yaml"), "/project", &[])?;
+
+        let files = discover(cwd.path(), &user, &system);
+        assert_eq!(
+            files
+                .files
+                .iter()
+                .map(|file| (file.path.clone(), file.layer))
+                .collect::<Vec<_>>(),
+            vec![
+                (system[1].join("rrcm/config.yaml"), Layer::System),
+                (system[0].join("rrcm/config.yaml"), Layer::System),
+                (user.join("config.yaml"), Layer::User),
+                (temp.path().join("project/.rrcm.yaml"), Layer::Project),
+            ]
+        );
+        assert_eq!(files.primary(), temp.path().join("project/.rrcm.yaml"));
+
+        let files = discover(temp.path(), &user, &system);
+        assert_eq!(files.primary(), user.join("config.yaml"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_partial_layer() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let user = temp.child("user.yaml");
+        user.write_str(
+            "version: 1\ndotfiles:\n  default: /user\nrelative_links: true\nrepos: []\n",
+        )?;
+        let project = temp.child(".rrcm.yaml");
+        project.write_str(
+            "repos:\n  - name: a\n    url: https://example.com/a.git\n    deploy: {}\n",
+        )?;
+
+        let files = ConfigFiles {
+            files: vec![
+                ConfigFile {
+                    path: user.to_path_buf(),
+                    layer: Layer::User,
+                },
+                ConfigFile {
+                    path: project.to_path_buf(),
+                    layer: Layer::Project,
+                },
+            ],
+        };
+        let (config, sources) = files.load_with_sources()?;
+        assert_eq!(config.dotfiles.default.as_deref(), Some("/user"));
+        assert!(config.relative_links);
+        assert_eq!(
+            config
+                .repos
+                .iter()
+                .map(|repo| repo.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a"]
+        );
+        assert_eq!(sources["a"], project.path());
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_layers() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let system = temp.path().join("system.yaml");
+        let user = temp.path().join("user.yaml");
+        write_config(
+            &system,
+            "/system",
+            &[
+                ("a", "https://example.com/system/a.git"),
+                ("b", "https://example.com/b.git"),
+            ],
+        )?;
+        write_config(
+            &user,
+            "/user",
+            &[
+                ("c", "https://example.com/c.git"),
+                ("a", "https://example.com/user/a.git"),
+            ],
+        )?;
+
+        let files = ConfigFiles {
+            files: vec![
+                ConfigFile {
+                    path: system.clone(),
+                    layer: Layer::System,
+                },
+                ConfigFile {
+                    path: user.clone(),
+                    layer: Layer::User,
+                },
+            ],
+        };
+        let (config, sources) = files.load_with_sources()?;
+        assert_eq!(config.dotfiles.default.as_deref(), Some("/user"));
+        assert_eq!(
+            config
+                .repos
+                .iter()
+                .map(|repo| (repo.name.as_str(), repo.url.as_deref().unwrap()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a", "https://example.com/user/a.git"),
+                ("b", "https://example.com/b.git"),
+                ("c", "https://example.com/c.git"),
+            ]
+        );
+        assert_eq!(sources["a"], user);
+        assert_eq!(sources["b"], system);
+        Ok(())
+    }
+}
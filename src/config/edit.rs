@@ -0,0 +1,702 @@
+//! Editing of config file.
+//!
+//! Repositories are added to and removed from the document in place,
+//! so comments and ordering of the existing document are preserved.
+//! - YAML: lines of `repos` are edited. Flow style `repos` is not supported except `[]`.
+//! - TOML: edited with `toml_edit`.
+//! - JSON: has no comment, so the document is rewritten keeping the ordering.
+use super::format::Format;
+use super::span::Positions;
+use super::Repository;
+use anyhow::{bail, ensure, Context as _, Result};
+use serde::Serialize;
+use serde_yml::Value;
+use std::path::Path;
+
+/// Add the repository to the end of `repos` of the config file.
+pub fn add_repository(path: &Path, repo: &Repository) -> Result<()> {
+    edit(path, |format, text| match format {
+        Format::Yaml => yaml_add(text, repo),
+        Format::Toml => toml_add(text, repo),
+        Format::Json => json_edit(text, |repos| {
+            repos.push(serde_yml::to_value(repo)?);
+            Ok(())
+        }),
+    })
+}
+
+/// Remove the repository from `repos` of the config file.
+pub fn remove_repository(path: &Path, name: &str) -> Result<()> {
+    edit(path, |format, text| match format {
+        Format::Yaml => yaml_remove(text, name),
+        Format::Toml => toml_remove(text, name),
+        Format::Json => json_edit(text, |repos| {
+            let index = find_repo(repos, name)?;
+            repos.remove(index);
+            Ok(())
+        }),
+    })
+}
+
+/// Set `version` of the config text in place, adding it to the top if not exists.
+/// JSON is not supported.
+pub fn set_version(format: Format, text: &str, version: u32) -> Result<String> {
+    match format {
+        Format::Yaml => Ok(yaml_set_version(text, version)),
+        Format::Toml => {
+            let mut document: toml_edit::DocumentMut = text.parse()?;
+            match document.get_mut("version") {
+                Some(item) => match item.as_value_mut() {
+                    // keep the comment of the line
+                    Some(value) => {
+                        let decor = value.decor().clone();
+                        *value = toml_edit::Value::from(i64::from(version));
+                        *value.decor_mut() = decor;
+                    }
+                    None => *item = toml_edit::value(i64::from(version)),
+                },
+                None => {
+                    // root keys are placed before tables
+                    let mut root = toml_edit::Table::new();
+                    root.insert("version", toml_edit::value(i64::from(version)));
+                    for (key, item) in document.iter() {
+                        root.insert(key, item.clone());
+                    }
+                    root.set_implicit(true);
+                    let decor = document.decor().clone();
+                    *document.as_table_mut() = root;
+                    *document.decor_mut() = decor;
+                }
+            }
+            Ok(document.to_string())
+        }
+        Format::Json => bail!("JSON can not be edited in place."),
+    }
+}
+
+/// Edit the config file. The edited document is checked before it is written.
+fn edit<F>(path: &Path, f: F) -> Result<()>
+where
+    F: FnOnce(Format, &str) -> Result<String>,
+{
+    let format = Format::from_path(path);
+    let text = std::fs::read_to_string(path)?;
+    let edited = f(format, &text).with_context(|| format!("Failed to edit {}", path.display()))?;
+    format
+        .parse(&edited)
+        .with_context(|| format!("Edited {} is invalid.", path.display()))?;
+    std::fs::write(path, edited)?;
+    Ok(())
+}
+
+fn find_repo(repos: &[Value], name: &str) -> Result<usize> {
+    repos
+        .iter()
+        .position(|repo| repo.get("name").and_then(Value::as_str) == Some(name))
+        .with_context(|| format!("Repository \"{}\" is not defined.", name))
+}
+
+fn json_edit<F>(text: &str, f: F) -> Result<String>
+where
+    F: FnOnce(&mut Vec<Value>) -> Result<()>,
+{
+    let mut document = Format::Json.parse(text)?;
+    let Value::Mapping(mapping) = &mut document else {
+        bail!("Config must be an object.");
+    };
+    let repos = mapping
+        .entry(Value::from("repos"))
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    let Value::Sequence(repos) = repos else {
+        bail!("`repos` must be an array.");
+    };
+    f(repos)?;
+    Format::Json.serialize(&document)
+}
+
+#[derive(Serialize)]
+struct Repos<'a> {
+    repos: [&'a Repository; 1],
+}
+
+fn toml_add(text: &str, repo: &Repository) -> Result<String> {
+    let mut document: toml_edit::DocumentMut = text.parse()?;
+    let serialized: toml_edit::DocumentMut = toml::to_string(&Repos { repos: [repo] })?.parse()?;
+    let mut table = serialized["repos"]
+        .as_array_of_tables()
+        .and_then(|repos| repos.get(0))
+        .context("Failed to serialize repository.")?
+        .clone();
+    // tables without position are placed after the preceding table
+    clear_positions(&mut table);
+    if !document.is_empty() {
+        table.decor_mut().set_prefix("\n");
+    }
+
+    match document.get_mut("repos") {
+        None => {
+            let mut repos = toml_edit::ArrayOfTables::new();
+            repos.push(table);
+            document.insert("repos", toml_edit::Item::ArrayOfTables(repos));
+        }
+        Some(toml_edit::Item::ArrayOfTables(repos)) => repos.push(table),
+        Some(item) => match item.as_array_mut() {
+            Some(repos) if repos.is_empty() => {
+                let mut repos = toml_edit::ArrayOfTables::new();
+                repos.push(table);
+                *item = toml_edit::Item::ArrayOfTables(repos);
+            }
+            Some(repos) => repos.push(table.into_inline_table()),
+            None => bail!("`repos` must be an array."),
+        },
+    }
+    Ok(document.to_string())
+}
+
+fn clear_positions(table: &mut toml_edit::Table) {
+    table.set_position(None);
+    for (_, item) in table.iter_mut() {
+        match item {
+            toml_edit::Item::Table(table) => clear_positions(table),
+            toml_edit::Item::ArrayOfTables(tables) => tables.iter_mut().for_each(clear_positions),
+            _ => {}
+        }
+    }
+}
+
+fn toml_remove(text: &str, name: &str) -> Result<String> {
+    let mut document: toml_edit::DocumentMut = text.parse()?;
+    let is_repo = |table: &dyn toml_edit::TableLike| {
+        table.get("name").and_then(|name| name.as_str()) == Some(name)
+    };
+    let removed = match document.get_mut("repos") {
+        Some(toml_edit::Item::ArrayOfTables(repos)) => {
+            let index = repos.iter().position(|table| is_repo(table));
+            index.map(|index| repos.remove(index)).is_some()
+        }
+        Some(item) => {
+            let repos = item.as_array_mut().context("`repos` must be an array.")?;
+            let index = repos
+                .iter()
+                .position(|repo| repo.as_inline_table().is_some_and(|table| is_repo(table)));
+            index.map(|index| repos.remove(index)).is_some()
+        }
+        None => false,
+    };
+    ensure!(removed, "Repository \"{}\" is not defined.", name);
+
+    // `repos` is required
+    if document
+        .get("repos")
+        .and_then(toml_edit::Item::as_array_of_tables)
+        .is_some_and(|repos| repos.is_empty())
+    {
+        document.insert("repos", toml_edit::value(toml_edit::Array::new()));
+    }
+    Ok(document.to_string())
+}
+
+/// `repos` of YAML document.
+struct YamlRepos<'a> {
+    lines: Vec<&'a str>,
+    /// Line index of `repos` key.
+    key: usize,
+    /// Line indexes of items.
+    items: Vec<usize>,
+    /// Line index where the block of `repos` ends (exclusive).
+    /// Comments and blank lines after the last item are not included.
+    end: usize,
+}
+
+impl<'a> YamlRepos<'a> {
+    /// Find `repos` in the document. `None` if `repos` is not defined.
+    fn parse(text: &'a str) -> Result<Option<(Self, Vec<Value>)>> {
+        let document = Format::Yaml.parse(text)?;
+        let positions = Positions::parse(text)?;
+        let Some(key) = positions.get("repos") else {
+            return Ok(None);
+        };
+        let lines = text.lines().collect::<Vec<_>>();
+        let key = key.line - 1;
+
+        let value = yaml_value(lines[key]);
+        let repos = match document.get("repos") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Sequence(repos)) if repos.is_empty() => Vec::new(),
+            Some(Value::Sequence(repos)) => {
+                ensure!(
+                    value.is_empty(),
+                    "Flow style `repos` is not supported. Edit it manually."
+                );
+                repos.clone()
+            }
+            Some(_) => bail!("`repos` must be a sequence."),
+        };
+
+        let mut items = Vec::new();
+        for i in 0..repos.len() {
+            let position = positions
+                .get(&format!("repos[{}]", i))
+                .context("Failed to locate repository.")?;
+            // items are located at their first key, find `-` of the item
+            let mut line = position.line - 1;
+            while line > key && !lines[line].trim_start().starts_with('-') {
+                line -= 1;
+            }
+            items.push(line);
+        }
+
+        let key_indent = indent(lines[key]);
+        let mut end = key + 1;
+        for (i, line) in lines.iter().enumerate().skip(key + 1) {
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            // sequence items may be at the same indentation as the key
+            if indent(line) < key_indent
+                || (indent(line) == key_indent && !trimmed.starts_with('-'))
+                || trimmed.starts_with("---")
+                || trimmed.starts_with("...")
+            {
+                break;
+            }
+            end = i + 1;
+        }
+
+        Ok(Some((
+            Self {
+                lines,
+                key,
+                items,
+                end,
+            },
+            repos,
+        )))
+    }
+
+    /// Indentation of items.
+    fn item_indent(&self) -> usize {
+        self.items
+            .first()
+            .map(|item| indent(self.lines[*item]))
+            .unwrap_or(indent(self.lines[self.key]) + 2)
+    }
+
+    /// First line of comments right before the line at the indentation or less.
+    fn leading_comments(&self, line: usize, indent_limit: usize) -> usize {
+        let mut start = line;
+        while start > self.key + 1 {
+            let prev = self.lines[start - 1];
+            if !prev.trim_start().starts_with('#') || indent(prev) > indent_limit {
+                break;
+            }
+            start -= 1;
+        }
+        start
+    }
+
+    /// `repos` key line with the value removed.
+    fn block_key(&self) -> String {
+        let line = self.lines[self.key];
+        let value = yaml_value(line);
+        if value.is_empty() {
+            return line.to_string();
+        }
+        let start = line.find(value).unwrap_or(line.len());
+        format!(
+            "{}{}",
+            line[..start].trim_end(),
+            &line[start + value.len()..]
+        )
+    }
+
+    fn join(lines: Vec<String>) -> String {
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Value after the key of the line, without comment.
+fn yaml_value(line: &str) -> &str {
+    let Some((_, value)) = line.split_once(':') else {
+        return "";
+    };
+    let value = match value.find(" #") {
+        Some(i) => &value[..i],
+        None => value,
+    };
+    value.trim()
+}
+
+fn yaml_set_version(text: &str, version: u32) -> String {
+    let mut lines = text.lines().map(str::to_string).collect::<Vec<_>>();
+    let line = format!("version: {}", version);
+    match lines
+        .iter()
+        .position(|l| indent(l) == 0 && l.starts_with("version:"))
+    {
+        Some(index) => {
+            // keep the comment of the line
+            let comment = lines[index]
+                .find(" #")
+                .map(|i| lines[index][i..].to_string());
+            lines[index] = line + comment.as_deref().unwrap_or_default();
+        }
+        None => {
+            // after the leading comments, directives and document start
+            let index = lines
+                .iter()
+                .position(|l| {
+                    let l = l.trim();
+                    !(l.is_empty() || l.starts_with('#') || l.starts_with('%') || l == "---")
+                })
+                .unwrap_or(lines.len());
+            lines.insert(index, line);
+        }
+    }
+    let mut text = lines.join("\n");
+    text.push('\n');
+    text
+}
+
+fn yaml_add(text: &str, repo: &Repository) -> Result<String> {
+    let item = serde_yml::to_string(&[repo])?;
+    let Some((repos, _)) = YamlRepos::parse(text)? else {
+        let mut text = text.to_string();
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push_str("repos:\n");
+        for line in item.lines() {
+            text.push_str(&format!("  {}\n", line));
+        }
+        return Ok(text);
+    };
+
+    let indent = " ".repeat(repos.item_indent());
+    let mut lines = repos
+        .lines
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    lines[repos.key] = repos.block_key();
+    // comments and blank lines at the end of the block belong to the next key
+    let end = repos.end;
+    lines.splice(
+        end..end,
+        item.lines().map(|line| format!("{}{}", indent, line)),
+    );
+    Ok(YamlRepos::join(lines))
+}
+
+fn yaml_remove(text: &str, name: &str) -> Result<String> {
+    let Some((repos, values)) = YamlRepos::parse(text)? else {
+        bail!("Repository \"{}\" is not defined.", name);
+    };
+    let index = find_repo(&values, name)?;
+    let indent = repos.item_indent();
+
+    let start = repos.leading_comments(repos.items[index], indent);
+    let end = match repos.items.get(index + 1) {
+        Some(next) => repos.leading_comments(*next, indent),
+        None => repos.end,
+    };
+    let mut lines = repos
+        .lines
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>();
+    lines.drain(start..end);
+    if values.len() == 1 {
+        // `repos` is required
+        let line = repos.lines[repos.key];
+        let colon = line.find(':').unwrap_or(line.len() - 1);
+        lines[repos.key] = format!("{} []{}", &line[..=colon], &line[colon + 1..]);
+    }
+    Ok(YamlRepos::join(lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::OsPath;
+    use indexmap::indexmap;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn repo(name: &str) -> Repository {
+        Repository {
+            name: name.to_string(),
+            url: Some(format!("https://example.com/{}.git", name)),
+            deploy: indexmap! {
+                "home".to_string() => OsPath {
+                    default: Some("/home/example".to_string()),
+                    ..OsPath::default()
+                }
+                .into(),
+            },
+            ..Repository::default()
+        }
+    }
+
+    const YAML: &str = indoc! {"
+        # config
+        dotfiles:
+          default: /dotfiles
+        repos:
+          # first
+          - name: example1 # inline
+            url: https://example.com/example1.git
+            deploy: {}
+
+          # second
+          - name: example2
+            url: https://example.com/example2.git
+            deploy: {}
+
+        # vars
+        vars:
+          NAME: value
+    "};
+
+    #[test]
+    fn test_yaml_add() -> Result<()> {
+        assert_eq!(
+            yaml_add(YAML, &repo("example3"))?,
+            indoc! {"
+                # config
+                dotfiles:
+                  default: /dotfiles
+                repos:
+                  # first
+                  - name: example1 # inline
+                    url: https://example.com/example1.git
+                    deploy: {}
+
+                  # second
+                  - name: example2
+                    url: https://example.com/example2.git
+                    deploy: {}
+                  - name: example3
+                    url: https://example.com/example3.git
+                    deploy:
+                      home:
+                        default: /home/example
+
+                # vars
+                vars:
+                  NAME: value
+            "}
+        );
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("repos: [] # comment\n", "repos: # comment\n  - name: example1\n")]
+    #[case("repos:\n", "repos:\n  - name: example1\n")]
+    #[case("dotfiles: {}\n", "dotfiles: {}\nrepos:\n  - name: example1\n")]
+    #[case(
+        "repos:\n- name: example0\n  url: ''\n  deploy: {}\nvars: {}\n",
+        "repos:\n- name: example0\n  url: ''\n  deploy: {}\n- name: example1\n"
+    )]
+    fn test_yaml_add_empty(#[case] text: &str, #[case] expected: &str) -> Result<()> {
+        let added = yaml_add(text, &repo("example1"))?;
+        assert!(added.starts_with(expected), "{}", added);
+        let document = Format::Yaml.parse(&added)?;
+        assert_eq!(
+            document["repos"].as_sequence().map(|repos| repos.len()),
+            Some(if text.contains("example0") { 2 } else { 1 })
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_yaml_add_flow() {
+        assert!(yaml_add(
+            "repos: [{name: a, url: b, deploy: {}}]\n",
+            &repo("example1")
+        )
+        .is_err());
+    }
+
+    #[rstest]
+    #[case(
+        "example1",
+        indoc! {"
+            # config
+            dotfiles:
+              default: /dotfiles
+            repos:
+              # second
+              - name: example2
+                url: https://example.com/example2.git
+                deploy: {}
+
+            # vars
+            vars:
+              NAME: value
+        "}
+    )]
+    #[case(
+        "example2",
+        indoc! {"
+            # config
+            dotfiles:
+              default: /dotfiles
+            repos:
+              # first
+              - name: example1 # inline
+                url: https://example.com/example1.git
+                deploy: {}
+
+
+            # vars
+            vars:
+              NAME: value
+        "}
+    )]
+    fn test_yaml_remove(#[case] name: &str, #[case] expected: &str) -> Result<()> {
+        assert_eq!(yaml_remove(YAML, name)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_yaml_remove_last() -> Result<()> {
+        let removed = yaml_remove(&yaml_remove(YAML, "example1")?, "example2")?;
+        assert!(removed.contains("repos: []\n"));
+        assert!(yaml_remove(YAML, "example3").is_err());
+        Ok(())
+    }
+
+    const TOML: &str = indoc! {r#"
+        # config
+        version = 1
+
+        [dotfiles]
+        default = "/dotfiles"
+
+        # first
+        [[repos]]
+        name = "example1"
+        url = "https://example.com/example1.git"
+
+        [repos.deploy.home]
+        default = "/home/example"
+
+        # vars
+        [vars]
+        NAME = "value"
+    "#};
+
+    #[rstest]
+    #[case(
+        "# rrcm\n\ndotfiles:\n  linux: /dotfiles # home\nrepos: []\n",
+        "# rrcm\n\nversion: 1\ndotfiles:\n  linux: /dotfiles # home\nrepos: []\n"
+    )]
+    #[case(
+        "---\nversion: 0 # old\nrepos: []\n",
+        "---\nversion: 1 # old\nrepos: []\n"
+    )]
+    fn test_yaml_set_version(#[case] text: &str, #[case] expected: &str) -> Result<()> {
+        assert_eq!(set_version(Format::Yaml, text, 1)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_toml_set_version() -> Result<()> {
+        let text = indoc! {r#"
+            # rrcm
+            [dotfiles]
+            linux = "/dotfiles" # home
+
+            [[repos]]
+            name = "example"
+        "#};
+        let edited = set_version(Format::Toml, text, 1)?;
+        assert_eq!(
+            edited,
+            indoc! {r#"
+                version = 1
+                # rrcm
+                [dotfiles]
+                linux = "/dotfiles" # home
+
+                [[repos]]
+                name = "example"
+            "#}
+        );
+        assert_eq!(
+            set_version(Format::Toml, "version = 0 # old\n", 1)?,
+            "version = 1 # old\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_toml_add_remove() -> Result<()> {
+        let added = toml_add(TOML, &repo("example2"))?;
+        assert_eq!(
+            added,
+            indoc! {r#"
+                # config
+                version = 1
+
+                [dotfiles]
+                default = "/dotfiles"
+
+                # first
+                [[repos]]
+                name = "example1"
+                url = "https://example.com/example1.git"
+
+                [repos.deploy.home]
+                default = "/home/example"
+
+                [[repos]]
+                name = "example2"
+                url = "https://example.com/example2.git"
+
+                [repos.deploy.home]
+                default = "/home/example"
+
+                # vars
+                [vars]
+                NAME = "value"
+            "#}
+        );
+        assert_eq!(toml_remove(&added, "example2")?, TOML);
+
+        let removed = toml_remove(TOML, "example1")?;
+        assert!(removed.contains("repos = []"));
+        assert_eq!(
+            Format::Toml.parse(&toml_add(&removed, &repo("example1"))?)?["repos"][0]["name"],
+            Value::from("example1")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_add_remove() -> Result<()> {
+        let text = "{\n  \"version\": 1,\n  \"repos\": [],\n  \"dotfiles\": {}\n}\n";
+        let added = json_edit(text, |repos| {
+            repos.push(serde_yml::to_value(repo("example1"))?);
+            Ok(())
+        })?;
+        assert!(added.find("\"repos\"") < added.find("\"dotfiles\""));
+        assert_eq!(
+            json_edit(&added, |repos| {
+                repos.remove(find_repo(repos, "example1")?);
+                Ok(())
+            })?,
+            text
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+//! Overrides by environment variables.
+//!
+//! Precedence is, from highest: command line flags, environment variables, config file and defaults.
+//! - `RRCM_CONFIG`: config file path, instead of discovered config files.
+//! - `RRCM_DOTFILES`: directory where repositories are downloaded, replacing `dotfiles` of the config file.
+//! - `RRCM_REPOS`: comma separated names of repositories to operate on.
+//!
+//! Empty values are ignored.
+use super::{AppConfig, OsPath};
+
+/// Environment variable of the config file path.
+pub const CONFIG_ENV: &str = "RRCM_CONFIG";
+/// Environment variable of the dotfiles directory.
+pub const DOTFILES_ENV: &str = "RRCM_DOTFILES";
+/// Environment variable of the selected repositories.
+pub const REPOS_ENV: &str = "RRCM_REPOS";
+
+pub(super) fn var(name: &str) -> Option<String> {
+    std::env::var(name)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// Replace `dotfiles` by `RRCM_DOTFILES`. The value is used on every OS.
+pub(super) fn apply_dotfiles(config: &mut AppConfig) {
+    if let Some(dotfiles) = var(DOTFILES_ENV) {
+        log::info!("dotfiles is overridden by {}: {}", DOTFILES_ENV, dotfiles);
+        config.dotfiles = OsPath {
+            default: Some(dotfiles),
+            ..OsPath::default()
+        };
+    }
+}
+
+/// Names of the repositories to operate on.
+/// `name` given by the argument, `RRCM_REPOS`, or `None` for all repositories.
+pub(super) fn selected_repos(name: Option<&str>) -> Option<Vec<String>> {
+    if let Some(name) = name {
+        return Some(vec![name.to_string()]);
+    }
+    var(REPOS_ENV).map(|names| parse_names(&names))
+}
+
+fn parse_names(names: &str) -> Vec<String> {
+    names
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("example1", vec!["example1"])]
+    #[case("example1, example2,", vec!["example1", "example2"])]
+    #[case(" , ", vec![])]
+    fn test_parse_names(#[case] names: &str, #[case] expected: Vec<&str>) {
+        assert_eq!(parse_names(names), expected);
+    }
+}
@@ -0,0 +1,226 @@
+//! Formats of config file.
+//!
+//! The format is selected by the extension of the file.
+//! - `.yaml`, `.yml`: YAML
+//! - `.toml`: TOML
+//! - `.json`: JSON
+//!
+//! Other extensions are read as YAML.
+//! Documents of every format are handled as [`serde_yml::Value`].
+use super::span::Position;
+use anyhow::{bail, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_yml::Value;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// File names of config file searched in config directory, in priority order.
+pub const CONFIG_FILE_NAMES: [&str; 3] = ["config.yaml", "config.toml", "config.json"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Self {
+        match path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .as_deref()
+        {
+            Some("toml") => Self::Toml,
+            Some("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Yaml => "yaml",
+            Self::Toml => "toml",
+            Self::Json => "json",
+        }
+    }
+
+    /// Parse the text into a document.
+    pub fn parse(&self, text: &str) -> Result<Value> {
+        self.deserialize(text)
+    }
+
+    pub fn deserialize<T>(&self, text: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        Ok(match self {
+            Self::Yaml => serde_yml::from_str(text)?,
+            Self::Toml => toml::from_str(text)?,
+            Self::Json => serde_json::from_str(text)?,
+        })
+    }
+
+    pub fn serialize<T>(&self, value: &T) -> Result<String>
+    where
+        T: Serialize,
+    {
+        Ok(match self {
+            Self::Yaml => serde_yml::to_string(value)?,
+            Self::Toml => toml::to_string_pretty(value)?,
+            Self::Json => serde_json::to_string_pretty(value)? + "\n",
+        })
+    }
+
+    /// Serialize the document.
+    /// TOML has no null, so null values are removed and their paths are returned.
+    pub fn serialize_document(&self, document: &Value) -> Result<(String, Vec<String>)> {
+        let mut removed = Vec::new();
+        if *self == Self::Toml {
+            let mut document = document.clone();
+            remove_nulls(&mut document, "", &mut removed);
+            return Ok((self.serialize(&document)?, removed));
+        }
+        Ok((self.serialize(document)?, removed))
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "yaml" | "yml" => Ok(Self::Yaml),
+            "toml" => Ok(Self::Toml),
+            "json" => Ok(Self::Json),
+            _ => bail!("Unsupported format {}. Use yaml, toml or json.", s),
+        }
+    }
+}
+
+/// Position and single line message of the error
+/// returned by [`Format::parse`] or [`Format::deserialize`].
+pub fn error_detail(e: &anyhow::Error, text: &str) -> (Option<Position>, String) {
+    if let Some(e) = e.downcast_ref::<serde_yml::Error>() {
+        let position = e.location().map(|location| Position {
+            line: location.line(),
+            column: location.column(),
+        });
+        return (position, e.to_string());
+    }
+    if let Some(e) = e.downcast_ref::<serde_json::Error>() {
+        let position = (e.line() > 0).then(|| Position {
+            line: e.line(),
+            column: e.column(),
+        });
+        return (position, e.to_string());
+    }
+    if let Some(e) = e.downcast_ref::<toml::de::Error>() {
+        let position = e.span().map(|span| {
+            let before = &text[..span.start.min(text.len())];
+            Position {
+                line: before.matches('\n').count() + 1,
+                column: before.len() - before.rfind('\n').map(|i| i + 1).unwrap_or(0) + 1,
+            }
+        });
+        return (position, e.message().trim().to_string());
+    }
+    (None, e.to_string())
+}
+
+fn remove_nulls(value: &mut Value, path: &str, removed: &mut Vec<String>) {
+    match value {
+        Value::Mapping(mapping) => {
+            mapping.retain(|key, value| {
+                let key = key.as_str().unwrap_or("?");
+                let path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                if value.is_null() {
+                    removed.push(path);
+                    return false;
+                }
+                remove_nulls(value, &path, removed);
+                true
+            });
+        }
+        Value::Sequence(sequence) => {
+            for (i, value) in sequence.iter_mut().enumerate() {
+                remove_nulls(value, &format!("{}[{}]", path, i), removed);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    const YAML: &str = indoc! {"
+        version: 1
+        dotfiles:
+          linux: /dotfiles
+        repos:
+        - name: example1
+          url: https://example.com/example1.git
+          deploy:
+            home:
+              linux: /home/example
+    "};
+
+    #[rstest]
+    #[case("config.yaml", Format::Yaml)]
+    #[case("config.yml", Format::Yaml)]
+    #[case("config.TOML", Format::Toml)]
+    #[case("config.json", Format::Json)]
+    #[case("config", Format::Yaml)]
+    fn test_from_path(#[case] path: &str, #[case] expected: Format) {
+        assert_eq!(Format::from_path(Path::new(path)), expected);
+    }
+
+    #[rstest]
+    #[case(Format::Yaml)]
+    #[case(Format::Toml)]
+    #[case(Format::Json)]
+    fn test_round_trip(#[case] format: Format) -> Result<()> {
+        let document = Format::Yaml.parse(YAML)?;
+        let (text, removed) = format.serialize_document(&document)?;
+        assert!(removed.is_empty());
+        assert_eq!(format.parse(&text)?, document);
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_toml_null() -> Result<()> {
+        let document = Format::Yaml.parse("dotfiles:\n  windows: ~\n  linux: /dotfiles\n")?;
+        let (text, removed) = Format::Toml.serialize_document(&document)?;
+        assert_eq!(text, "[dotfiles]\nlinux = \"/dotfiles\"\n");
+        assert_eq!(removed, vec!["dotfiles.windows"]);
+        Ok(())
+    }
+
+    #[rstest]
+    #[case(Format::Yaml, "dotfiles:\n  linux: [\n", 3)]
+    #[case(Format::Toml, "version = 1\n[dotfiles\n", 2)]
+    #[case(Format::Json, "{\n  \"version\": 1,\n  \"dotfiles\": }\n", 3)]
+    fn test_error_detail(#[case] format: Format, #[case] text: &str, #[case] line: usize) {
+        let e = format.parse(text).unwrap_err();
+        let (position, message) = error_detail(&e, text);
+        assert_eq!(position.map(|p| p.line), Some(line));
+        assert!(!message.contains('\n'));
+    }
+}
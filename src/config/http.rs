@@ -0,0 +1,283 @@
+//! HTTP download of config files.
+//!
+//! Credentials are sent only to the host of the requested url, and dropped on redirects to other hosts.
+//! - headers: given as `Name: value`.
+//! - token: environment variable `RRCM_TOKEN`, or the one given, sent as `Authorization: Bearer <token>`.
+//! - netrc: login and password for the host in `NETRC`, or `~/.netrc` (`~/_netrc` on Windows),
+//!   sent as basic authentication.
+//!
+//! Headers take precedence over the token, and the token over netrc.
+//! The token and netrc are not sent over plain `http://`, except to loopback hosts.
+use anyhow::{bail, Context as _, Result};
+use reqwest::blocking::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, LOCATION};
+use reqwest::redirect::Policy;
+use std::path::PathBuf;
+use url::Url;
+
+/// Default environment variable of the bearer token.
+pub const TOKEN_ENV: &str = "RRCM_TOKEN";
+
+const MAX_REDIRECTS: usize = 10;
+
+/// Options of HTTP requests.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct HttpOptions {
+    /// Headers in `Name: value` form.
+    pub headers: Vec<String>,
+    /// Environment variable of the bearer token. [`TOKEN_ENV`] if not set.
+    pub token_env: Option<String>,
+}
+
+impl HttpOptions {
+    /// Headers sent to the host of the url, and basic authentication from netrc
+    /// unless `Authorization` is given by headers or the token.
+    /// The token and netrc are dropped unless the url is secure.
+    fn credentials(&self, url: &Url) -> Result<(HeaderMap, Option<(String, String)>)> {
+        let mut headers = HeaderMap::new();
+        for header in &self.headers {
+            let (name, value) = parse_header(header)?;
+            headers.append(name, value);
+        }
+        if headers.contains_key(AUTHORIZATION) {
+            return Ok((headers, None));
+        }
+
+        let secure = is_secure(url);
+        let token_env = self.token_env.as_deref().unwrap_or(TOKEN_ENV);
+        if let Some(token) = std::env::var(token_env).ok().filter(|t| !t.is_empty()) {
+            if !secure {
+                log::warn!("Token in {} is not sent over insecure {}", token_env, url);
+                return Ok((headers, None));
+            }
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token.trim()))
+                .with_context(|| format!("Invalid token in {}", token_env))?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+            return Ok((headers, None));
+        }
+
+        let netrc = match url.host_str() {
+            Some(host) => netrc_credentials(host)?,
+            None => None,
+        };
+        if netrc.is_some() && !secure {
+            log::warn!("Credentials in netrc are not sent over insecure {}", url);
+            return Ok((headers, None));
+        }
+        Ok((headers, netrc))
+    }
+}
+
+/// Download the url. Credentials are sent only while the request stays on the same host.
+pub fn get(url: &Url, options: &HttpOptions) -> Result<Vec<u8>> {
+    let client = Client::builder().redirect(Policy::none()).build()?;
+    let (headers, basic_auth) = options.credentials(url)?;
+
+    let mut current = url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        let mut request = client.get(current.clone());
+        if same_origin(&current, url) {
+            request = request.headers(headers.clone());
+            if let Some((login, password)) = &basic_auth {
+                request = request.basic_auth(login, Some(password));
+            }
+        }
+        let res = request
+            .send()
+            .with_context(|| format!("Failed to download {}", current))?;
+        if res.status().is_redirection() {
+            let Some(location) = res.headers().get(LOCATION) else {
+                bail!("Redirect without location from {}", current);
+            };
+            current = current.join(location.to_str()?)?;
+            log::info!("Redirected to {}", current);
+            continue;
+        }
+        let res = res
+            .error_for_status()
+            .with_context(|| format!("Failed to download {}", url))?;
+        return Ok(res.bytes()?.to_vec());
+    }
+    bail!("Too many redirects from {}", url)
+}
+
+/// Whether credentials can be sent to the url, i.e. `https://` or a loopback host.
+fn is_secure(url: &Url) -> bool {
+    match url.host() {
+        _ if url.scheme() == "https" => true,
+        Some(url::Host::Domain(domain)) => domain.eq_ignore_ascii_case("localhost"),
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+fn same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme()
+        && a.host_str() == b.host_str()
+        && a.port_or_known_default() == b.port_or_known_default()
+}
+
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue)> {
+    let Some((name, value)) = header.split_once(':') else {
+        bail!("Invalid header \"{}\". Use \"Name: value\".", header);
+    };
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .with_context(|| format!("Invalid header name \"{}\"", name.trim()))?;
+    let mut value = HeaderValue::from_str(value.trim())
+        .with_context(|| format!("Invalid value of header {}", name))?;
+    value.set_sensitive(true);
+    Ok((name, value))
+}
+
+fn netrc_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("NETRC") {
+        return Some(PathBuf::from(path));
+    }
+    let home = dirs::home_dir()?;
+    let names: &[&str] = if cfg!(target_os = "windows") {
+        &["_netrc", ".netrc"]
+    } else {
+        &[".netrc"]
+    };
+    names
+        .iter()
+        .map(|name| home.join(name))
+        .find(|p| p.exists())
+}
+
+/// Login and password for the host in the netrc file.
+fn netrc_credentials(host: &str) -> Result<Option<(String, String)>> {
+    let Some(path) = netrc_path().filter(|path| path.exists()) else {
+        return Ok(None);
+    };
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(parse_netrc(&text, host))
+}
+
+/// Find the entry of the host, or the `default` entry.
+fn parse_netrc(text: &str, host: &str) -> Option<(String, String)> {
+    let mut tokens = text
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .flat_map(str::split_whitespace);
+
+    let mut found = None;
+    let mut matched = false;
+    let (mut login, mut password) = (None, None);
+    while let Some(token) = tokens.next() {
+        match token {
+            "machine" | "default" => {
+                if matched {
+                    break;
+                }
+                matched = token == "default" || tokens.next() == Some(host);
+                (login, password) = (None, None);
+            }
+            "login" => login = tokens.next(),
+            "password" => password = tokens.next(),
+            // macro definitions are not supported
+            "macdef" => break,
+            _ => {}
+        }
+        if matched {
+            if let (Some(login), Some(password)) = (login, password) {
+                found = Some((login.to_string(), password.to_string()));
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    const NETRC: &str = indoc! {"
+        # comment
+        machine example.com login alice password secret
+        machine gist.example.com
+          login bob
+          password hunter2
+        default login anonymous password guest
+    "};
+
+    #[rstest]
+    #[case("example.com", Some(("alice", "secret")))]
+    #[case("gist.example.com", Some(("bob", "hunter2")))]
+    #[case("other.example.com", Some(("anonymous", "guest")))]
+    fn test_parse_netrc(#[case] host: &str, #[case] expected: Option<(&str, &str)>) {
+        assert_eq!(
+            parse_netrc(NETRC, host),
+            expected.map(|(l, p)| (l.to_string(), p.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_parse_netrc_no_default() {
+        assert_eq!(
+            parse_netrc("machine example.com login a password b\n", "other"),
+            None
+        );
+    }
+
+    #[rstest]
+    #[case("X-Token: abc", Some(("x-token", "abc")))]
+    #[case("Authorization:Bearer abc ", Some(("authorization", "Bearer abc")))]
+    #[case("X-Token abc", None)]
+    #[case("X Token: abc", None)]
+    fn test_parse_header(#[case] header: &str, #[case] expected: Option<(&str, &str)>) {
+        let parsed = parse_header(header).ok();
+        assert_eq!(
+            parsed
+                .as_ref()
+                .map(|(name, value)| (name.as_str(), value.to_str().unwrap())),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("https://example.com/a", true)]
+    #[case("http://example.com/a", false)]
+    #[case("http://localhost:8080/a", true)]
+    #[case("http://127.0.0.1:8080/a", true)]
+    #[case("http://[::1]:8080/a", true)]
+    #[case("http://192.168.0.1/a", false)]
+    fn test_is_secure(#[case] url: &str, #[case] expected: bool) {
+        assert_eq!(is_secure(&Url::parse(url).unwrap()), expected);
+    }
+
+    #[test]
+    fn test_credentials_insecure() -> Result<()> {
+        // PATH is set on every platform, so it stands for a token set in the environment
+        let options = HttpOptions {
+            headers: vec![String::from("X-Token: abc")],
+            token_env: Some(String::from("PATH")),
+        };
+        let (headers, basic_auth) = options.credentials(&Url::parse("http://example.com/a")?)?;
+        assert!(!headers.contains_key(AUTHORIZATION));
+        assert!(headers.contains_key("x-token"));
+        assert_eq!(basic_auth, None);
+
+        let (headers, _) = options.credentials(&Url::parse("https://example.com/a")?)?;
+        assert!(headers.contains_key(AUTHORIZATION));
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("https://example.com/a", "https://example.com:443/b", true)]
+    #[case("https://example.com/a", "http://example.com/a", false)]
+    #[case("https://example.com/a", "https://gist.example.com/a", false)]
+    #[case("http://127.0.0.1:8080/a", "http://127.0.0.1:8081/a", false)]
+    fn test_same_origin(#[case] a: &str, #[case] b: &str, #[case] expected: bool) {
+        assert_eq!(
+            same_origin(&Url::parse(a).unwrap(), &Url::parse(b).unwrap()),
+            expected
+        );
+    }
+}
@@ -0,0 +1,352 @@
+//! Machine local override of config file.
+//!
+//! `config.local.yaml` next to `config.yaml` is merged into it when loaded.
+//! `config.local.toml` or `config.local.json` can be used as well, whatever the format of the config file is,
+//! so that it is kept after `config convert`. It is an error if more than one of them exists.
+//! - Mappings are merged by key, and `~` removes the key.
+//! - Repositories are matched by `name`. `repos` can also be a mapping from the name to the repository,
+//!   where `~` removes the repository.
+//! - Deploy targets and host rules are matched by key. `dotfiles` and each deploy target are replaced as a whole,
+//!   since paths of other OS would otherwise take precedence.
+//!   `~` of a host rule is kept as is, and `~` of a host removes the rules of the host.
+//!
+//! ```yaml
+//! dotfiles:
+//!   default: /work/dotfiles
+//! repos:
+//!   - name: example1
+//!     deploy:
+//!       .config: ~
+//!   - name: machine
+//!     path: /opt/machine-dotfiles
+//!     deploy:
+//!       home:
+//!         default: ${HOME}
+//! ```
+use super::Format;
+use anyhow::{bail, Context as _, Result};
+use serde_yml::{Mapping, Value};
+use std::path::{Path, PathBuf};
+
+/// Extensions of the local override, whatever the format of the config file is.
+const EXTENSIONS: [&str; 4] = ["yaml", "yml", "toml", "json"];
+
+/// Path of the local override of the config file, e.g. `config.local.yaml` of `config.toml`.
+/// `None` if it does not exist.
+pub fn local_path(config: &Path) -> Result<Option<PathBuf>> {
+    let stem = config
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let found = EXTENSIONS
+        .iter()
+        .map(|ext| config.with_file_name(format!("{}.local.{}", stem, ext)))
+        .filter(|path| path.exists())
+        .collect::<Vec<_>>();
+    if found.len() > 1 {
+        bail!(
+            "Local overrides of {} are found: {}. Remove all but one.",
+            config.display(),
+            found
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    Ok(found.into_iter().next())
+}
+
+/// Read the local override of the config file. `None` if it does not exist.
+pub(super) fn read(config: &Path) -> Result<Option<(PathBuf, Value)>> {
+    let Some(path) = local_path(config)? else {
+        return Ok(None);
+    };
+    let document = Format::from_path(&path)
+        .parse(&std::fs::read_to_string(&path)?)
+        .with_context(|| format!("Failed to load {}", path.display()))?;
+    Ok(Some((path, document)))
+}
+
+/// Merge `local` into `document`. Returns the names of the repositories added by `local`.
+pub(super) fn merge(document: &mut Value, local: Value) -> Result<Vec<String>> {
+    let mut added = Vec::new();
+    let Value::Mapping(local) = local else {
+        if local.is_null() {
+            return Ok(added);
+        }
+        bail!("Local config must be a mapping.");
+    };
+    let Some(document) = document.as_mapping_mut() else {
+        bail!("Config must be a mapping.");
+    };
+    for (key, value) in local {
+        match key.as_str() {
+            Some("repos") => added = merge_repos(document, value)?,
+            Some("vars") => merge_entry(document, key, value, 1),
+            _ => merge_entry(document, key, value, 0),
+        }
+    }
+    Ok(added)
+}
+
+/// Merge the value of the key. `depth` is the number of levels of mappings merged by key,
+/// and deeper values are replaced.
+fn merge_entry(mapping: &mut Mapping, key: Value, value: Value, depth: usize) {
+    if value.is_null() {
+        mapping.remove(&key);
+        return;
+    }
+    match mapping.get_mut(&key) {
+        Some(base) => merge_value(base, value, depth),
+        None => {
+            mapping.insert(key, value);
+        }
+    }
+}
+
+fn merge_value(base: &mut Value, value: Value, depth: usize) {
+    match (base.as_mapping_mut(), value) {
+        (Some(base), Value::Mapping(value)) if depth > 0 => {
+            for (key, value) in value {
+                merge_entry(base, key, value, depth - 1);
+            }
+        }
+        (_, value) => *base = value,
+    }
+}
+
+fn merge_repository(base: &mut Mapping, local: Mapping) {
+    for (key, value) in local {
+        match key.as_str() {
+            Some("deploy") => merge_entry(base, key, value, 1),
+            Some("hosts") => merge_hosts(base, key, value),
+            _ => merge_entry(base, key, value, 0),
+        }
+    }
+}
+
+/// `~` of a host rule means not to deploy the target on the host, so it is kept.
+fn merge_hosts(base: &mut Mapping, key: Value, value: Value) {
+    let Value::Mapping(hosts) = value else {
+        merge_entry(base, key, value, 0);
+        return;
+    };
+    let Some(Value::Mapping(base)) = base.get_mut(&key) else {
+        base.insert(key, Value::Mapping(hosts));
+        return;
+    };
+    for (host, rules) in hosts {
+        match (base.get_mut(&host), rules) {
+            (Some(Value::Mapping(base)), Value::Mapping(rules)) => base.extend(rules),
+            (_, rules) => merge_entry(base, host, rules, 0),
+        }
+    }
+}
+
+fn merge_repos(document: &mut Mapping, local: Value) -> Result<Vec<String>> {
+    let local = match local {
+        Value::Sequence(repos) => repos
+            .into_iter()
+            .map(|repo| {
+                let name = repo
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .context("Repository in local config must have name.")?
+                    .to_string();
+                Ok((name, repo))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Value::Mapping(repos) => repos
+            .into_iter()
+            .map(|(name, repo)| {
+                let name = name
+                    .as_str()
+                    .context("Repository name must be a string.")?
+                    .to_string();
+                Ok((name, repo))
+            })
+            .collect::<Result<Vec<_>>>()?,
+        Value::Null => Vec::new(),
+        _ => bail!("repos must be a sequence or a mapping."),
+    };
+
+    let repos = document
+        .entry(Value::from("repos"))
+        .or_insert_with(|| Value::Sequence(Vec::new()));
+    let Some(repos) = repos.as_sequence_mut() else {
+        bail!("repos must be a sequence.");
+    };
+
+    let mut added = Vec::new();
+    for (name, repo) in local {
+        let index = repos
+            .iter()
+            .position(|r| r.get("name").and_then(Value::as_str) == Some(name.as_str()));
+        match (index, repo) {
+            (Some(index), Value::Null) => {
+                repos.remove(index);
+            }
+            (None, Value::Null) => log::warn!("Repository {} to remove is not found.", name),
+            (Some(index), Value::Mapping(repo)) => {
+                if let Some(base) = repos[index].as_mapping_mut() {
+                    merge_repository(base, repo);
+                }
+            }
+            (None, Value::Mapping(mut repo)) => {
+                repo.insert(Value::from("name"), Value::from(name.as_str()));
+                repos.push(Value::Mapping(repo));
+                added.push(name);
+            }
+            (_, _) => bail!("Repository {} must be a mapping or ~.", name),
+        }
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("config.yaml", &[], None)]
+    #[case("config.yaml", &["config.local.yaml"], Some("config.local.yaml"))]
+    #[case("config.toml", &["config.local.yaml"], Some("config.local.yaml"))]
+    #[case("config.yaml", &["config.local.json"], Some("config.local.json"))]
+    #[case(".rrcm.yaml", &[".rrcm.local.toml", "config.local.yaml"], Some(".rrcm.local.toml"))]
+    fn test_local_path(
+        #[case] config: &str,
+        #[case] files: &[&str],
+        #[case] expected: Option<&str>,
+    ) -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        for file in files {
+            std::fs::write(dir.path().join(file), "")?;
+        }
+        assert_eq!(
+            local_path(&dir.path().join(config))?,
+            expected.map(|expected| dir.path().join(expected))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_path_ambiguous() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("config.local.yaml"), "")?;
+        std::fs::write(dir.path().join("config.local.toml"), "")?;
+        assert!(local_path(&dir.path().join("config.yaml")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge() -> Result<()> {
+        let mut document: Value = serde_yml::from_str(indoc! {"
+            version: 1
+            vars:
+              A: a
+              B: b
+            dotfiles:
+              linux: /dotfiles
+              mac: /dotfiles
+            repos:
+              - name: example1
+                url: https://example.com/example1.git
+                branch: main
+                deploy:
+                  home:
+                    linux: /home
+                  .config:
+                    linux: /config
+                hosts:
+                  laptop:
+                    home: ~
+                  desktop:
+                    home: ~
+              - name: example2
+                url: https://example.com/example2.git
+                deploy: {}
+        "})?;
+        let local: Value = serde_yml::from_str(indoc! {"
+            vars:
+              A: ~
+              C: c
+            dotfiles:
+              default: /work
+            repos:
+              - name: example1
+                branch: ~
+                deploy:
+                  home:
+                    default: /home2
+                  .config: ~
+                hosts:
+                  laptop:
+                    .config: ~
+                  desktop: ~
+              - name: example3
+                path: /opt/example3
+                deploy: {}
+        "})?;
+        let added = merge(&mut document, local)?;
+        assert_eq!(added, vec!["example3"]);
+
+        let expected: Value = serde_yml::from_str(indoc! {"
+            version: 1
+            vars:
+              B: b
+              C: c
+            dotfiles:
+              default: /work
+            repos:
+              - name: example1
+                url: https://example.com/example1.git
+                deploy:
+                  home:
+                    default: /home2
+                hosts:
+                  laptop:
+                    home: ~
+                    .config: ~
+              - name: example2
+                url: https://example.com/example2.git
+                deploy: {}
+              - name: example3
+                path: /opt/example3
+                deploy: {}
+        "})?;
+        assert_eq!(document, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_repos_mapping() -> Result<()> {
+        let mut document: Value = serde_yml::from_str(indoc! {"
+            repos:
+              - name: example1
+                url: https://example.com/example1.git
+              - name: example2
+                url: https://example.com/example2.git
+        "})?;
+        let local: Value = serde_yml::from_str(indoc! {"
+            repos:
+              example1: ~
+              example3:
+                url: https://example.com/example3.git
+        "})?;
+        assert_eq!(merge(&mut document, local)?, vec!["example3"]);
+
+        let expected: Value = serde_yml::from_str(indoc! {"
+            repos:
+              - name: example2
+                url: https://example.com/example2.git
+              - url: https://example.com/example3.git
+                name: example3
+        "})?;
+        assert_eq!(document, expected);
+        Ok(())
+    }
+}
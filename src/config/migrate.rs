@@ -0,0 +1,149 @@
+//! Migration of config file.
+//!
+//! Config documents are upgraded step by step from their `version` to [`CURRENT_VERSION`].
+//! Documents without `version` are version 0.
+use anyhow::{bail, Context as _, Result};
+use serde_yml::{Mapping, Value};
+
+/// Version of config file supported by this rrcm.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Migration from version `from` to `from + 1`.
+struct Migration {
+    from: u32,
+    /// Migrate the document and return the changes made.
+    migrate: fn(&mut Mapping) -> Vec<String>,
+    /// The file is rewritten from the migrated document, losing comments and formatting.
+    /// Otherwise only `version` is updated in place.
+    rewrite: bool,
+}
+
+const MIGRATIONS: &[Migration] = &[Migration {
+    from: 0,
+    migrate: migrate_v0,
+    rewrite: false,
+}];
+
+/// Version 0 to 1: `version` is added.
+fn migrate_v0(_: &mut Mapping) -> Vec<String> {
+    vec!["Add `version`.".to_string()]
+}
+
+/// Version of the config document.
+pub fn version(document: &Value) -> Result<u32> {
+    match document.get("version") {
+        None | Some(Value::Null) => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .with_context(|| format!("Invalid config version {:?}", version)),
+    }
+}
+
+/// Whether migrating the document of the version requires rewriting the file.
+pub fn requires_rewrite(version: u32) -> bool {
+    MIGRATIONS
+        .iter()
+        .any(|migration| migration.from >= version && migration.rewrite)
+}
+
+/// Migrate the config document to the current version.
+/// Returns the changes made, empty if the document is already the current version.
+pub fn migrate(document: &mut Value) -> Result<Vec<String>> {
+    let mut version = version(document)?;
+    if version > CURRENT_VERSION {
+        bail!(
+            "Config version {} is not supported. Supported version is {} or earlier. Please update rrcm.",
+            version,
+            CURRENT_VERSION
+        );
+    }
+    let Value::Mapping(mapping) = document else {
+        bail!("Config must be a mapping.");
+    };
+
+    let mut changes = Vec::new();
+    while version < CURRENT_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+            .with_context(|| format!("Migration from version {} not found", version))?;
+        changes.extend(
+            (migration.migrate)(mapping)
+                .into_iter()
+                .map(|change| format!("{} -> {}: {}", version, version + 1, change)),
+        );
+        version += 1;
+    }
+
+    if !changes.is_empty() {
+        // version is placed at the top of the document
+        mapping.shift_remove("version");
+        let mut migrated = Mapping::new();
+        migrated.insert(Value::from("version"), Value::from(version));
+        migrated.extend(std::mem::take(mapping));
+        *mapping = migrated;
+    }
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_migrations() {
+        // every version before the current one can be migrated
+        for version in 0..CURRENT_VERSION {
+            assert!(MIGRATIONS.iter().any(|migration| migration.from == version));
+        }
+    }
+
+    #[test]
+    fn test_migrate_v0() -> Result<()> {
+        let mut document: Value = serde_yml::from_str(indoc! {"
+            dotfiles:
+              linux: /dotfiles
+            repos: []
+        "})?;
+        assert_eq!(version(&document)?, 0);
+
+        let changes = migrate(&mut document)?;
+        assert_eq!(changes, vec!["0 -> 1: Add `version`."]);
+        assert_eq!(
+            serde_yml::to_string(&document)?,
+            indoc! {"
+                version: 1
+                dotfiles:
+                  linux: /dotfiles
+                repos: []
+            "}
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_current() -> Result<()> {
+        let mut document: Value = serde_yml::from_str(&format!(
+            "version: {}\ndotfiles: {{}}\nrepos: []\n",
+            CURRENT_VERSION
+        ))?;
+        assert!(migrate(&mut document)?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_error() -> Result<()> {
+        let mut document: Value = serde_yml::from_str("version: 999\n")?;
+        assert!(migrate(&mut document)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Config version 999 is not supported."));
+
+        let mut document: Value = serde_yml::from_str("version: one\n")?;
+        assert!(migrate(&mut document).is_err());
+        Ok(())
+    }
+}
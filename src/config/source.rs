@@ -0,0 +1,210 @@
+//! Where the config file came from.
+//!
+//! `rrcm init` reads the config file from a [`Location`],
+//! and records it in `<stem>.source.yaml` next to the config file.
+use super::http::{self, HttpOptions};
+use super::verify::Verification;
+use anyhow::{bail, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use url::Url;
+
+/// Location of a config file to initialize from.
+/// - `http://`, `https://`: downloaded
+/// - `file://`, other strings: local path
+/// - `-`: standard input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Url(Url),
+    Path(PathBuf),
+    Stdin,
+}
+
+impl Location {
+    /// Read the content.
+    pub fn read(&self, http: &HttpOptions) -> Result<Vec<u8>> {
+        match self {
+            Location::Url(url) => http::get(url, http),
+            Location::Path(path) => {
+                std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+            }
+            Location::Stdin => {
+                let mut content = Vec::new();
+                std::io::stdin()
+                    .read_to_end(&mut content)
+                    .context("Failed to read stdin")?;
+                Ok(content)
+            }
+        }
+    }
+
+    /// Location of the file with the extension appended, e.g. a signature.
+    pub fn with_extension(&self, extension: &str) -> Result<Location> {
+        match self {
+            Location::Url(url) => {
+                let mut url = url.clone();
+                url.set_path(&format!("{}.{}", url.path(), extension));
+                Ok(Location::Url(url))
+            }
+            Location::Path(path) => {
+                let mut path = path.clone().into_os_string();
+                path.push(format!(".{}", extension));
+                Ok(Location::Path(path.into()))
+            }
+            Location::Stdin => bail!("{} of stdin can not be read.", extension),
+        }
+    }
+
+    /// Absolute location to record. `None` for stdin, which can not be read again.
+    fn to_record(&self) -> Result<Option<String>> {
+        Ok(match self {
+            Location::Url(url) => Some(url.to_string()),
+            Location::Path(path) => Some(dunce::canonicalize(path)?.display().to_string()),
+            Location::Stdin => None,
+        })
+    }
+}
+
+impl FromStr for Location {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "-" {
+            return Ok(Location::Stdin);
+        }
+        match Url::parse(s) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(Location::Url(url)),
+            Ok(url) if url.scheme() == "file" => match url.to_file_path() {
+                Ok(path) => Ok(Location::Path(path)),
+                Err(_) => bail!("Invalid file url {}", s),
+            },
+            // drive letter of Windows path is parsed as a scheme
+            Ok(url) if url.scheme().len() > 1 => {
+                bail!(
+                    "Unsupported scheme {}. Use http, https or file.",
+                    url.scheme()
+                )
+            }
+            _ => Ok(Location::Path(PathBuf::from(s))),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Location::Url(url) => write!(f, "{}", url),
+            Location::Path(path) => write!(f, "{}", path.display()),
+            Location::Stdin => write!(f, "stdin"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigSource {
+    /// URL or absolute path the config file was read from.
+    pub url: String,
+    /// SHA-256 digest of the content.
+    pub sha256: String,
+    /// Verification applied to the download.
+    #[serde(flatten)]
+    pub verification: Verification,
+}
+
+impl ConfigSource {
+    /// Source of the content read from the location. `None` for stdin.
+    pub fn new(
+        location: &Location,
+        sha256: String,
+        verification: &Verification,
+    ) -> Result<Option<Self>> {
+        Ok(location.to_record()?.map(|url| Self {
+            url,
+            sha256,
+            verification: verification.clone(),
+        }))
+    }
+
+    /// Path of the record for the config file.
+    pub fn path(config: &Path) -> PathBuf {
+        let stem = config
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        config.with_file_name(format!("{}.source.yaml", stem))
+    }
+
+    /// Read the record of the config file. `None` if the config file was not downloaded.
+    pub fn read(config: &Path) -> Result<Option<Self>> {
+        let path = Self::path(config);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text = std::fs::read_to_string(&path)?;
+        let source = serde_yml::from_str(&text)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(source))
+    }
+
+    pub fn write(&self, config: &Path) -> Result<()> {
+        std::fs::write(Self::path(config), serde_yml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("https://example.com/config.yaml", Some(Location::Url(Url::parse("https://example.com/config.yaml").unwrap())))]
+    #[case("-", Some(Location::Stdin))]
+    #[case("config.yaml", Some(Location::Path(PathBuf::from("config.yaml"))))]
+    #[case(
+        "../usb/config.yaml",
+        Some(Location::Path(PathBuf::from("../usb/config.yaml")))
+    )]
+    #[case("ftp://example.com/config.yaml", None)]
+    fn test_location_from_str(#[case] s: &str, #[case] expected: Option<Location>) {
+        assert_eq!(s.parse::<Location>().ok(), expected);
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_location_file_url() -> Result<()> {
+        assert_eq!(
+            "file:///media/usb/config.yaml".parse::<Location>()?,
+            Location::Path(PathBuf::from("/media/usb/config.yaml"))
+        );
+        assert_eq!(
+            Location::Path(PathBuf::from("/media/usb/config.yaml")).with_extension("minisig")?,
+            Location::Path(PathBuf::from("/media/usb/config.yaml.minisig"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_write() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.toml");
+        assert_eq!(ConfigSource::read(&config)?, None);
+
+        let source = ConfigSource {
+            url: "https://example.com/config.toml".to_string(),
+            sha256: "0".repeat(64),
+            verification: Verification {
+                minisign_key: Some("RWQ".to_string()),
+                ..Verification::default()
+            },
+        };
+        source.write(&config)?;
+        assert!(temp.path().join("config.source.yaml").exists());
+        assert_eq!(ConfigSource::read(&config)?, Some(source));
+        Ok(())
+    }
+}
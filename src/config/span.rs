@@ -0,0 +1,203 @@
+//! Positions of nodes in YAML document.
+//!
+//! Nodes are identified by the path from the document root,
+//! e.g. `repos[0].deploy.home.linux`.
+use std::collections::HashMap;
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::{Marker, ScanError};
+
+/// Position in the document. Line and column are 1-indexed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl From<Marker> for Position {
+    fn from(mark: Marker) -> Self {
+        Self {
+            line: mark.line(),
+            column: mark.col() + 1,
+        }
+    }
+}
+
+/// Positions of nodes in YAML document.
+/// Mapping values are located at their keys.
+#[derive(Debug, Default)]
+pub struct Positions {
+    positions: HashMap<String, Position>,
+}
+
+impl Positions {
+    pub fn parse(text: &str) -> Result<Self, ScanError> {
+        let mut receiver = Receiver::default();
+        Parser::new_from_str(text).load(&mut receiver, false)?;
+        Ok(Self {
+            positions: receiver.positions,
+        })
+    }
+
+    /// Position of the node.
+    /// If the node does not exist, the position of the nearest ancestor is returned.
+    pub fn get(&self, path: &str) -> Option<Position> {
+        let mut path = path;
+        loop {
+            if let Some(position) = self.positions.get(path) {
+                return Some(*position);
+            }
+            path = &path[..path.rfind(['.', '['])?];
+        }
+    }
+}
+
+enum Frame {
+    /// `located` is false until the position of the mapping is recorded.
+    /// Mappings in sequences are located at their first key.
+    Mapping {
+        key: Option<String>,
+        located: bool,
+    },
+    Sequence {
+        index: usize,
+    },
+}
+
+#[derive(Default)]
+struct Receiver {
+    stack: Vec<Frame>,
+    path: Vec<String>,
+    positions: HashMap<String, Position>,
+}
+
+impl Receiver {
+    fn child_path(&self, segment: &str) -> String {
+        let mut path = self.path.concat();
+        if !path.is_empty() && !segment.starts_with('[') {
+            path.push('.');
+        }
+        path.push_str(segment);
+        path
+    }
+
+    fn push_segment(&mut self, segment: String) {
+        if self.path.is_empty() || segment.starts_with('[') {
+            self.path.push(segment);
+        } else {
+            self.path.push(format!(".{}", segment));
+        }
+    }
+
+    fn begin_node(&mut self, mark: Marker) {
+        match self.stack.last_mut() {
+            Some(Frame::Sequence { index }) => {
+                let segment = format!("[{}]", index);
+                self.positions
+                    .insert(self.child_path(&segment), mark.into());
+                self.push_segment(segment);
+            }
+            Some(Frame::Mapping { key, .. }) => {
+                // complex keys are not supported
+                let segment = key.get_or_insert_with(|| "?".to_string()).clone();
+                self.push_segment(segment);
+            }
+            None => {}
+        }
+    }
+
+    fn end_node(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Sequence { index }) => {
+                *index += 1;
+                self.path.pop();
+            }
+            Some(Frame::Mapping { key, .. }) => {
+                *key = None;
+                self.path.pop();
+            }
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for Receiver {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, ..) => {
+                if let Some(Frame::Mapping { key: None, located }) = self.stack.last() {
+                    if !located {
+                        self.positions.insert(self.path.concat(), mark.into());
+                    }
+                    self.positions.insert(self.child_path(&value), mark.into());
+                    self.stack.pop();
+                    self.stack.push(Frame::Mapping {
+                        key: Some(value),
+                        located: true,
+                    });
+                } else {
+                    self.begin_node(mark);
+                    self.end_node();
+                }
+            }
+            Event::Alias(_) => {
+                self.begin_node(mark);
+                self.end_node();
+            }
+            Event::MappingStart(..) => {
+                let located = !matches!(self.stack.last(), Some(Frame::Sequence { .. }));
+                self.begin_node(mark);
+                self.stack.push(Frame::Mapping { key: None, located });
+            }
+            Event::SequenceStart(..) => {
+                self.begin_node(mark);
+                self.stack.push(Frame::Sequence { index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.end_node();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+    use rstest::rstest;
+
+    const YAML: &str = indoc! {r#"
+        ---
+        dotfiles:
+          linux: "${HOME}/.dotfiles"
+        repos:
+          - name: example1
+            url: 'git@github:example/example1'
+            deploy:
+              home:
+                linux: "${HOME}"
+          - { name: example2, url: 'git@github:example/example2' }
+    "#};
+
+    #[rstest]
+    #[case("dotfiles", Some((2, 1)))]
+    #[case("dotfiles.linux", Some((3, 3)))]
+    #[case("repos", Some((4, 1)))]
+    #[case("repos[0]", Some((5, 5)))]
+    #[case("repos[0].name", Some((5, 5)))]
+    #[case("repos[0].url", Some((6, 5)))]
+    #[case("repos[0].deploy.home", Some((8, 7)))]
+    #[case("repos[0].deploy.home.linux", Some((9, 9)))]
+    #[case("repos[0].deploy.config.linux", Some((7, 5)))]
+    #[case("repos[1]", Some((10, 7)))]
+    #[case("repos[1].url", Some((10, 23)))]
+    #[case("vars.HOME", None)]
+    fn test_positions(#[case] path: &str, #[case] expected: Option<(usize, usize)>) {
+        let positions = Positions::parse(YAML).unwrap();
+        assert_eq!(
+            positions.get(path),
+            expected.map(|(line, column)| Position { line, column })
+        );
+    }
+}
@@ -0,0 +1,727 @@
+//! Validation of config file.
+//!
+//! Reports syntax errors and semantic problems with the position in the file.
+use super::format::{error_detail, Format};
+use super::migrate::{self, CURRENT_VERSION};
+use super::span::{Position, Positions};
+use super::{
+    resolve_include_path, resolve_var, AppConfig, DeployTarget, IncludedConfig, Repository,
+};
+use crate::fs;
+use crate::when;
+use anyhow::Result;
+use core::fmt::{self, Display};
+use indexmap::IndexMap;
+use serde::de::DeserializeOwned;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Problem found in config file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub file: PathBuf,
+    pub position: Option<Position>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// Location of the problem, `file:line:column`.
+    pub fn location(&self) -> String {
+        match self.position {
+            Some(position) => format!(
+                "{}:{}:{}",
+                self.file.display(),
+                position.line,
+                position.column
+            ),
+            None => format!("{}", self.file.display()),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}: {}",
+            self.location(),
+            self.severity,
+            self.message
+        )
+    }
+}
+
+/// Validate config file and included files.
+/// Returns all problems found.
+pub fn validate_app_config<P>(path: P) -> Result<Vec<Diagnostic>>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let mut validator = Validator::default();
+
+    let Some((config, positions)) = validator.parse::<AppConfig>(path, true) else {
+        return Ok(validator.diagnostics);
+    };
+    validator.visited.push(dunce::canonicalize(path)?);
+
+    let vars = validator.validate_vars(path, &positions, &config.vars);
+    let dotfiles = match config.dotfiles.to_pathbuf_with(&vars) {
+        Ok(dotfiles) => Some(dotfiles),
+        Err(e) => {
+            validator.push(
+                path,
+                positions.get("dotfiles"),
+                Severity::Error,
+                format!("Invalid dotfiles path. {}", e),
+            );
+            None
+        }
+    };
+
+    let context = Context {
+        vars,
+        dotfiles: dotfiles.as_deref(),
+    };
+    validator.validate_repos(path, &positions, &config.repos, &context);
+    validator.validate_includes(path, &positions, &config.include, &context)?;
+
+    Ok(validator.diagnostics)
+}
+
+struct Context<'a> {
+    vars: IndexMap<String, String>,
+    dotfiles: Option<&'a Path>,
+}
+
+#[derive(Default)]
+struct Validator {
+    diagnostics: Vec<Diagnostic>,
+    /// Repository name and where it is defined.
+    repos: IndexMap<String, (PathBuf, Option<Position>)>,
+    visited: Vec<PathBuf>,
+}
+
+impl Validator {
+    fn push(
+        &mut self,
+        file: &Path,
+        position: Option<Position>,
+        severity: Severity,
+        message: String,
+    ) {
+        self.diagnostics.push(Diagnostic {
+            file: file.to_path_buf(),
+            position,
+            severity,
+            message,
+        });
+    }
+
+    /// Parse config file. Syntax errors are reported.
+    /// Old version of config is reported if `check_version` is true.
+    fn parse<T>(&mut self, path: &Path, check_version: bool) -> Option<(T, Positions)>
+    where
+        T: DeserializeOwned,
+    {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) => {
+                self.push(path, None, Severity::Error, format!("{}", e));
+                return None;
+            }
+        };
+        let format = Format::from_path(path);
+        // positions are available only in YAML
+        let positions = match format {
+            Format::Yaml => Positions::parse(&text),
+            Format::Toml | Format::Json => Ok(Positions::default()),
+        };
+        let positions = match positions {
+            Ok(positions) => positions,
+            Err(e) => {
+                self.push(
+                    path,
+                    Some(e.marker().to_owned().into()),
+                    Severity::Error,
+                    e.info().to_string(),
+                );
+                return None;
+            }
+        };
+        let mut document = match format.parse(&text) {
+            Ok(document) => document,
+            Err(e) => {
+                let (position, message) = error_detail(&e, &text);
+                self.push(path, position, Severity::Error, message);
+                return None;
+            }
+        };
+        let version = match migrate::version(&document) {
+            Ok(version) => version,
+            Err(e) => {
+                self.push(
+                    path,
+                    positions.get("version"),
+                    Severity::Error,
+                    format!("{}", e),
+                );
+                return None;
+            }
+        };
+        if version != CURRENT_VERSION {
+            if let Err(e) = migrate::migrate(&mut document) {
+                self.push(
+                    path,
+                    positions.get("version"),
+                    Severity::Error,
+                    format!("{}", e),
+                );
+                return None;
+            }
+            if check_version {
+                self.push(
+                    path,
+                    positions.get("version"),
+                    Severity::Warning,
+                    format!(
+                        "Config version {} is outdated. Run `rrcm config migrate` to update.",
+                        version
+                    ),
+                );
+            }
+        }
+        let result = if version == CURRENT_VERSION {
+            // deserialize from text to keep the location of errors
+            format.deserialize::<T>(&text)
+        } else {
+            serde_yml::from_value::<T>(document).map_err(anyhow::Error::from)
+        };
+        match result {
+            Ok(config) => Some((config, positions)),
+            Err(e) => {
+                let (position, message) = error_detail(&e, &text);
+                self.push(path, position, Severity::Error, message);
+                None
+            }
+        }
+    }
+
+    /// Expand user defined variables. Variables that can not be expanded are reported.
+    fn validate_vars(
+        &mut self,
+        path: &Path,
+        positions: &Positions,
+        vars: &IndexMap<String, String>,
+    ) -> IndexMap<String, String> {
+        let mut resolved = IndexMap::new();
+        for name in vars.keys() {
+            if let Err(e) = resolve_var(name, vars, &mut resolved, &mut Vec::new()) {
+                self.push(
+                    path,
+                    positions.get(&format!("vars.{}", name)),
+                    Severity::Error,
+                    format!("{:#}", e),
+                );
+            }
+        }
+        resolved
+    }
+
+    fn validate_includes(
+        &mut self,
+        path: &Path,
+        positions: &Positions,
+        includes: &[String],
+        context: &Context,
+    ) -> Result<()> {
+        for (index, include) in includes.iter().enumerate() {
+            let position = positions.get(&format!("include[{}]", index));
+            let include_path = match resolve_include_path(path, include) {
+                Ok(include_path) => include_path,
+                Err(e) => {
+                    self.push(path, position, Severity::Error, format!("{:#}", e));
+                    continue;
+                }
+            };
+            if !include_path.exists() {
+                self.push(
+                    path,
+                    position,
+                    Severity::Error,
+                    format!("Included file {} does not exist.", include_path.display()),
+                );
+                continue;
+            }
+
+            let canonical = dunce::canonicalize(&include_path)?;
+            if self.visited.contains(&canonical) {
+                continue;
+            }
+            self.visited.push(canonical);
+
+            let Some((included, included_positions)) =
+                self.parse::<IncludedConfig>(&include_path, false)
+            else {
+                continue;
+            };
+            self.validate_repos(&include_path, &included_positions, &included.repos, context);
+            self.validate_includes(
+                &include_path,
+                &included_positions,
+                &included.include,
+                context,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn validate_repos(
+        &mut self,
+        path: &Path,
+        positions: &Positions,
+        repos: &[Repository],
+        context: &Context,
+    ) {
+        for (index, repo) in repos.iter().enumerate() {
+            let repo_key = format!("repos[{}]", index);
+            let name_position = positions.get(&format!("{}.name", repo_key));
+
+            if let Some((defined_file, defined_position)) = self.repos.get(&repo.name) {
+                let defined = Diagnostic {
+                    file: defined_file.clone(),
+                    position: *defined_position,
+                    severity: Severity::Error,
+                    message: String::new(),
+                };
+                let message = format!(
+                    "Repository \"{}\" is already defined at {}.",
+                    repo.name,
+                    defined.location()
+                );
+                self.push(path, name_position, Severity::Error, message);
+            } else {
+                self.repos
+                    .insert(repo.name.clone(), (path.to_path_buf(), name_position));
+            }
+
+            if let Err(e) = repo.pin() {
+                let key = [("rev", &repo.rev), ("tag", &repo.tag)]
+                    .into_iter()
+                    .find(|(_, value)| value.is_some())
+                    .map(|(key, _)| key)
+                    .unwrap_or("branch");
+                let position = positions.get(&format!("{}.{}", repo_key, key));
+                self.push(path, position, Severity::Error, e.to_string());
+            }
+
+            if let Err(e) = repo.source() {
+                let position = positions
+                    .get(&format!("{}.path", repo_key))
+                    .filter(|_| repo.path.is_some())
+                    .or(name_position);
+                self.push(path, position, Severity::Error, e.to_string());
+            }
+
+            self.validate_when(path, positions, &repo_key, &repo.when);
+
+            let repo_path = match context
+                .dotfiles
+                .map(|dotfiles| repo.to_pathbuf_with(dotfiles, &context.vars))
+                .transpose()
+            {
+                Ok(repo_path) => repo_path,
+                Err(e) => {
+                    // error of dir takes precedence over path
+                    let key = repo
+                        .dir
+                        .as_ref()
+                        .and_then(|dir| {
+                            dir.os_key()
+                                .filter(|_| dir.to_pathbuf_with(&context.vars).is_err())
+                        })
+                        .map(|os_key| format!("{}.dir.{}", repo_key, os_key))
+                        .unwrap_or_else(|| format!("{}.path", repo_key));
+                    self.push(
+                        path,
+                        positions.get(&key),
+                        Severity::Error,
+                        format!("Invalid repository directory. {}", e),
+                    );
+                    None
+                }
+            };
+            let repo_exists = repo_path.as_ref().is_some_and(|path| path.exists());
+            if let Some(repo_path) = repo_path.as_ref().filter(|_| !repo_exists) {
+                self.push(
+                    path,
+                    positions.get(&repo_key),
+                    Severity::Warning,
+                    format!(
+                        "Repository directory {} does not exist. Deploy sources are not checked.",
+                        repo_path.display()
+                    ),
+                );
+            }
+
+            let mut targets = repo
+                .deploy
+                .iter()
+                .map(|(name, to)| (format!("{}.deploy.{}", repo_key, name), name, to))
+                .collect::<Vec<_>>();
+            for (pattern, deploy) in &repo.hosts {
+                for (name, to) in deploy {
+                    if let Some(to) = to {
+                        let key = format!("{}.hosts.{}.{}", repo_key, pattern, name);
+                        targets.push((key, name, to));
+                    }
+                }
+            }
+            for (key, name, to) in targets {
+                let source = repo_path
+                    .as_ref()
+                    .filter(|_| repo_exists)
+                    .map(|repo_path| repo_path.join(name));
+                self.validate_target(path, positions, &key, source.as_deref(), to, context);
+            }
+        }
+    }
+
+    /// Check syntax of the condition.
+    fn validate_when(
+        &mut self,
+        path: &Path,
+        positions: &Positions,
+        key: &str,
+        condition: &Option<String>,
+    ) {
+        if let Some(Err(e)) = condition.as_deref().map(when::parse) {
+            self.push(
+                path,
+                positions.get(&format!("{}.when", key)),
+                Severity::Error,
+                format!("{:#}", e),
+            );
+        }
+    }
+
+    fn validate_target(
+        &mut self,
+        path: &Path,
+        positions: &Positions,
+        key: &str,
+        source: Option<&Path>,
+        to: &DeployTarget,
+        context: &Context,
+    ) {
+        self.validate_when(path, positions, key, &to.when);
+        let to = &to.path;
+        if let Some(source) = source.filter(|source| !source.is_dir()) {
+            self.push(
+                path,
+                positions.get(key),
+                Severity::Error,
+                format!(
+                    "Deploy source directory {} does not exist.",
+                    source.display()
+                ),
+            );
+        }
+
+        // not deployed on this OS
+        let Some(os_key) = to.os_key() else {
+            return;
+        };
+        let os_key = format!("{}.{}", key, os_key);
+        let destination = match to.to_pathbuf_with(&context.vars) {
+            Ok(destination) => destination,
+            Err(e) => {
+                self.push(
+                    path,
+                    positions.get(&os_key),
+                    Severity::Error,
+                    format!("Invalid deploy destination. {}", e),
+                );
+                return;
+            }
+        };
+
+        let Some(dotfiles) = context.dotfiles else {
+            return;
+        };
+        if let (Ok(destination), Ok(dotfiles)) =
+            (fs::absolutize(&destination), fs::absolutize(dotfiles))
+        {
+            if destination.starts_with(&dotfiles) {
+                self.push(
+                    path,
+                    positions.get(&os_key),
+                    Severity::Error,
+                    format!(
+                        "Deploy destination {} is inside dotfiles directory {}.",
+                        destination.display(),
+                        dotfiles.display()
+                    ),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::formatdoc;
+    use pretty_assertions::assert_eq;
+    use rstest::rstest;
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<String> {
+        diagnostics
+            .iter()
+            .map(|diagnostic| {
+                format!(
+                    "{}: {}: {}",
+                    diagnostic
+                        .position
+                        .map(|p| format!("{}:{}", p.line, p.column))
+                        .unwrap_or_default(),
+                    diagnostic.severity,
+                    diagnostic.message
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_validate_syntax_error() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.yaml");
+        std::fs::write(&config, "dotfiles:\n  linux: [\n")?;
+
+        let diagnostics = validate_app_config(&config)?;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].position.map(|p| p.line), Some(3));
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("config.toml", "version = 1\nrepos = []\n[dotfiles\n", 3)]
+    #[case("config.json", "{\n  \"version\": 1,\n  \"repos\": [,\n}\n", 3)]
+    #[case("config.toml", "version = 1\n[dotfiles]\ndefault = \"/dotfiles\"\n", 1)]
+    fn test_validate_syntax_error_format(
+        #[case] name: &str,
+        #[case] text: &str,
+        #[case] line: usize,
+    ) -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join(name);
+        std::fs::write(&config, text)?;
+
+        let diagnostics = validate_app_config(&config)?;
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].position.map(|p| p.line), Some(line));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_pin() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        std::fs::create_dir(temp.path().join("example"))?;
+        let config = temp.path().join("config.yaml");
+        std::fs::write(
+            &config,
+            formatdoc! {"
+                version: 1
+                dotfiles:
+                  default: {}
+                repos:
+                  - name: example
+                    url: 'https://example.com/example.git'
+                    branch: main
+                    tag: v1.0
+                    deploy: {{}}
+            ", temp.path().display()},
+        )?;
+
+        let diagnostics = validate_app_config(&config)?;
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "8:5: error: Only one of branch, tag and rev can be set for repository \"example\"."
+                    .to_string()
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_source() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        std::fs::create_dir_all(temp.path().join("local"))?;
+        std::fs::create_dir_all(temp.path().join("neither"))?;
+        let config = temp.path().join("config.yaml");
+        std::fs::write(
+            &config,
+            formatdoc! {"
+                version: 1
+                dotfiles:
+                  default: {}
+                repos:
+                  - name: both
+                    url: 'https://example.com/example.git'
+                    path: local
+                    deploy: {{}}
+                  - name: neither
+                    deploy: {{}}
+                  - name: secure
+                    url: 'https://example.com/secure.git'
+                    dir:
+                      default: ${{RRCM_UNDEFINED_VAR}}
+                    deploy: {{}}
+            ", temp.path().display()},
+        )?;
+
+        let diagnostics = validate_app_config(&config)?;
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "7:5: error: Only one of url and path can be set for repository \"both\"."
+                    .to_string(),
+                "9:5: error: Either url or path must be set for repository \"neither\"."
+                    .to_string(),
+                "14:7: error: Invalid repository directory. env var RRCM_UNDEFINED_VAR not found"
+                    .to_string(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_when() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        std::fs::create_dir_all(temp.path().join("example/home"))?;
+        let config = temp.path().join("config.yaml");
+        std::fs::write(
+            &config,
+            formatdoc! {"
+                version: 1
+                dotfiles:
+                  default: {}
+                repos:
+                  - name: example
+                    url: 'https://example.com/example.git'
+                    when: os(linux) &&
+                    deploy:
+                      home:
+                        default: /home/example
+                        when: unknown(x)
+            ", temp.path().display()},
+        )?;
+
+        let diagnostics = validate_app_config(&config)?;
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "7:5: error: Invalid condition `os(linux) &&`: Unexpected end of condition"
+                    .to_string(),
+                "11:9: error: Invalid condition `unknown(x)`: Unknown function `unknown`"
+                    .to_string(),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_missing_field() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let config = temp.path().join("config.yaml");
+        std::fs::write(&config, "version: 1\ndotfiles:\n  default: /dotfiles\n")?;
+
+        let diagnostics = validate_app_config(&config)?;
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("missing field `repos`"));
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_validate() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let tmpdir = temp.path().to_string_lossy();
+        std::fs::create_dir_all(temp.path().join("dotfiles/example1/home"))?;
+        std::fs::write(
+            temp.path().join("shared.yaml"),
+            formatdoc! {"
+                repos:
+                  - name: example1
+                    url: 'https://example.com/example1.git'
+                    deploy: {{}}
+            "},
+        )?;
+
+        let config = temp.path().join("config.yaml");
+        std::fs::write(
+            &config,
+            formatdoc! {r#"
+                version: 1
+                include:
+                  - shared.yaml
+                  - missing.yaml
+                vars:
+                  DOTFILES: "{tmpdir}/dotfiles"
+                  LOOP: "${{LOOP}}"
+                dotfiles:
+                  default: "${{DOTFILES}}"
+                repos:
+                  - name: example1
+                    url: 'https://example.com/example1.git'
+                    deploy:
+                      home:
+                        default: "{tmpdir}/home"
+                      config:
+                        default: "${{RRCM_UNDEFINED_VAR}}"
+                      inside:
+                        default: "${{DOTFILES}}/inside"
+                  - name: example2
+                    url: 'https://example.com/example2.git'
+                    deploy:
+                      home:
+                        default: "{tmpdir}/home"
+            "#},
+        )?;
+
+        let diagnostics = validate_app_config(&config)?;
+        assert_eq!(
+            messages(&diagnostics),
+            vec![
+                "7:3: error: Failed to expand variable LOOP: Variable cycle detected: LOOP -> LOOP".to_string(),
+                format!("16:7: error: Deploy source directory {tmpdir}/dotfiles/example1/config does not exist."),
+                "17:9: error: Invalid deploy destination. env var RRCM_UNDEFINED_VAR not found".to_string(),
+                format!("18:7: error: Deploy source directory {tmpdir}/dotfiles/example1/inside does not exist."),
+                format!("19:9: error: Deploy destination {tmpdir}/dotfiles/inside is inside dotfiles directory {tmpdir}/dotfiles."),
+                format!("20:5: warning: Repository directory {tmpdir}/dotfiles/example2 does not exist. Deploy sources are not checked."),
+                format!("2:5: error: Repository \"example1\" is already defined at {}:11:5.", config.display()),
+                format!("4:5: error: Included file {tmpdir}/missing.yaml does not exist."),
+            ]
+        );
+        assert_eq!(diagnostics[6].file, temp.path().join("shared.yaml"),);
+        Ok(())
+    }
+}
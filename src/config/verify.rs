@@ -0,0 +1,234 @@
+//! Integrity verification of downloaded config files.
+//!
+//! - sha256: SHA-256 digest of the content in hex.
+//! - minisign: signature `<url>.minisig` made by `minisign -S`. Only pre-hashed signatures are accepted.
+//! - ssh: signature `<url>.sig` made by `ssh-keygen -Y sign -n file`,
+//!   verified by `ssh-keygen -Y verify` with an allowed signers file.
+//!
+//! The digest of the verified content is recorded next to the config file,
+//! so that a later download can tell if the remote config has changed.
+use anyhow::{bail, ensure, Context as _, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Namespace of SSH signatures.
+const SSH_NAMESPACE: &str = "file";
+
+/// How to verify the downloaded content.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Verification {
+    /// Expected SHA-256 digest in hex. Only used for the first download.
+    #[serde(skip)]
+    pub sha256: Option<String>,
+    /// minisign public key in base64, or path of the public key file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minisign_key: Option<String>,
+    /// Allowed signers file of SSH signatures.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_allowed_signers: Option<PathBuf>,
+    /// Identity of the SSH signer in the allowed signers file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_identity: Option<String>,
+}
+
+impl Verification {
+    /// Verify the content. `signature` returns the signature of the extension.
+    /// Returns the SHA-256 digest of the content.
+    pub fn verify<F>(&self, content: &[u8], mut signature: F) -> Result<String>
+    where
+        F: FnMut(&str) -> Result<Vec<u8>>,
+    {
+        let digest = sha256(content);
+        if let Some(expected) = &self.sha256 {
+            ensure!(
+                digest.eq_ignore_ascii_case(expected.trim()),
+                "SHA-256 mismatch. expected {}, but got {}.",
+                expected.trim(),
+                digest
+            );
+        }
+        if let Some(key) = &self.minisign_key {
+            verify_minisign(content, key, &signature("minisig")?)
+                .context("minisign verification failed")?;
+        }
+        if let Some(allowed_signers) = &self.ssh_allowed_signers {
+            let Some(identity) = &self.ssh_identity else {
+                bail!("SSH signer identity is not specified.");
+            };
+            verify_ssh(content, allowed_signers, identity, &signature("sig")?)
+                .context("SSH signature verification failed")?;
+        }
+        Ok(digest)
+    }
+}
+
+/// SHA-256 digest in lower case hex.
+pub fn sha256(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn verify_minisign(content: &[u8], key: &str, signature: &[u8]) -> Result<()> {
+    let key = if Path::new(key).is_file() {
+        minisign_verify::PublicKey::from_file(key)
+    } else {
+        minisign_verify::PublicKey::from_base64(key.trim())
+    }
+    .map_err(|e| anyhow::anyhow!("Invalid public key. {}", e))?;
+    let signature = minisign_verify::Signature::decode(&String::from_utf8_lossy(signature))
+        .map_err(|e| anyhow::anyhow!("Invalid signature. {}", e))?;
+    key.verify(content, &signature, false)
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+fn verify_ssh(
+    content: &[u8],
+    allowed_signers: &Path,
+    identity: &str,
+    signature: &[u8],
+) -> Result<()> {
+    // ssh-keygen reads the signature from a file, created exclusively and readable only by the user.
+    // the file is removed when dropped.
+    let mut signature_file = tempfile::Builder::new()
+        .prefix("rrcm-")
+        .suffix(".sig")
+        .tempfile()
+        .context("Failed to create signature file")?;
+    signature_file.write_all(signature)?;
+    signature_file.flush()?;
+
+    let mut child = Command::new("ssh-keygen")
+        .args(["-Y", "verify", "-n", SSH_NAMESPACE, "-I", identity, "-f"])
+        .arg(allowed_signers)
+        .arg("-s")
+        .arg(signature_file.path())
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to execute ssh-keygen")?;
+    child
+        .stdin
+        .take()
+        .context("Failed to open stdin of ssh-keygen")?
+        .write_all(content)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let message = [output.stderr, output.stdout]
+            .iter()
+            .map(|s| String::from_utf8_lossy(s).trim().to_string())
+            .find(|s| !s.is_empty())
+            .unwrap_or_default();
+        bail!("{}", message);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine;
+    use blake2::Blake2b512;
+    use ed25519_dalek::{Signer, SigningKey};
+    use pretty_assertions::assert_eq;
+
+    const CONTENT: &[u8] = b"version: 1\n";
+
+    /// minisign public key and pre-hashed signature of the content.
+    fn minisign(content: &[u8]) -> (String, Vec<u8>) {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let key_id = [1, 2, 3, 4, 5, 6, 7, 8];
+        let base64 = base64::engine::general_purpose::STANDARD;
+
+        let public_key = [b"Ed".as_slice(), &key_id, key.verifying_key().as_bytes()].concat();
+        let hash = Blake2b512::digest(content);
+        let signature = key.sign(&hash).to_bytes();
+        let trusted_comment = "timestamp:0\tfile:config.yaml";
+        let global = key
+            .sign(&[signature.as_slice(), trusted_comment.as_bytes()].concat())
+            .to_bytes();
+        let signature = format!(
+            "untrusted comment: signature from rrcm test key\n{}\ntrusted comment: {}\n{}\n",
+            base64.encode([b"ED".as_slice(), &key_id, &signature].concat()),
+            trusted_comment,
+            base64.encode(global)
+        );
+        (base64.encode(public_key), signature.into_bytes())
+    }
+
+    #[test]
+    fn test_sha256() -> Result<()> {
+        let digest = sha256(CONTENT);
+        assert_eq!(digest.len(), 64);
+
+        let verification = Verification {
+            sha256: Some(digest.to_uppercase()),
+            ..Verification::default()
+        };
+        assert_eq!(verification.verify(CONTENT, |_| unreachable!())?, digest);
+
+        let e = verification.verify(b"version: 2\n", |_| unreachable!());
+        assert!(e.unwrap_err().to_string().starts_with("SHA-256 mismatch."));
+        Ok(())
+    }
+
+    #[test]
+    fn test_minisign() -> Result<()> {
+        let (key, signature) = minisign(CONTENT);
+        let verification = Verification {
+            minisign_key: Some(key),
+            ..Verification::default()
+        };
+        verification.verify(CONTENT, |ext| {
+            assert_eq!(ext, "minisig");
+            Ok(signature.clone())
+        })?;
+
+        let e = verification
+            .verify(b"version: 2\n", |_| Ok(signature.clone()))
+            .unwrap_err();
+        assert_eq!(e.to_string(), "minisign verification failed");
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "windows"))]
+    #[test]
+    fn test_ssh() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let key = temp.path().join("id_ed25519");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "rrcm", "-f"])
+            .arg(&key)
+            .status()?;
+        assert!(status.success());
+        let public_key = std::fs::read_to_string(key.with_extension("pub"))?;
+        let allowed_signers = temp.path().join("allowed_signers");
+        std::fs::write(&allowed_signers, format!("rrcm@example.com {}", public_key))?;
+
+        let config = temp.path().join("config.yaml");
+        std::fs::write(&config, CONTENT)?;
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-Y", "sign", "-n", SSH_NAMESPACE, "-f"])
+            .arg(&key)
+            .arg(&config)
+            .status()?;
+        assert!(status.success());
+        let signature = std::fs::read(temp.path().join("config.yaml.sig"))?;
+
+        let verification = Verification {
+            ssh_allowed_signers: Some(allowed_signers),
+            ssh_identity: Some("rrcm@example.com".to_string()),
+            ..Verification::default()
+        };
+        verification.verify(CONTENT, |_| Ok(signature.clone()))?;
+        assert!(verification
+            .verify(b"version: 2\n", |_| Ok(signature.clone()))
+            .is_err());
+        Ok(())
+    }
+}
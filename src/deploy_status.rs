@@ -1,15 +1,21 @@
 use crate::fs;
+use crate::state::CopyRecord;
 use core::fmt::{self, Display};
 use core::hash::Hash;
-use std::fs::read_link;
 use std::path::Path;
 
 #[derive(Debug, Eq, Clone)]
 pub enum DeployStatus {
     UnDeployed,
     Deployed,
-    Conflict { cause: String },
+    Conflict {
+        cause: String,
+    },
     UnManaged,
+    /// The copy is changed since copied.
+    Modified,
+    /// The file in the repository is updated since copied, and the copy is not changed.
+    Outdated,
 }
 impl PartialEq for DeployStatus {
     fn eq(&self, other: &Self) -> bool {
@@ -18,6 +24,8 @@ impl PartialEq for DeployStatus {
             (DeployStatus::UnDeployed, DeployStatus::UnDeployed)
                 | (DeployStatus::Deployed, DeployStatus::Deployed)
                 | (DeployStatus::UnManaged, DeployStatus::UnManaged)
+                | (DeployStatus::Modified, DeployStatus::Modified)
+                | (DeployStatus::Outdated, DeployStatus::Outdated)
                 | (DeployStatus::Conflict { .. }, DeployStatus::Conflict { .. })
         )
     }
@@ -30,6 +38,8 @@ impl Hash for DeployStatus {
             DeployStatus::Deployed => 1.hash(state),
             DeployStatus::UnManaged => 2.hash(state),
             DeployStatus::Conflict { .. } => 3.hash(state),
+            DeployStatus::Modified => 4.hash(state),
+            DeployStatus::Outdated => 5.hash(state),
         }
     }
 }
@@ -41,6 +51,8 @@ impl Display for DeployStatus {
             DeployStatus::Deployed => write!(f, "Deployed"),
             DeployStatus::UnManaged => write!(f, "UnManaged"),
             DeployStatus::Conflict { .. } => write!(f, "Conflict"),
+            DeployStatus::Modified => write!(f, "Modified"),
+            DeployStatus::Outdated => write!(f, "Outdated"),
         }
     }
 }
@@ -64,8 +76,25 @@ where
         }
     }
 
-    let abs_to_link = fs::absolutize(read_link(to).unwrap()).unwrap();
-    if fs::absolutize(from).unwrap() != abs_to_link {
+    let abs_to_link = match fs::link_target(&to) {
+        Ok(link) => link,
+        Err(e) => {
+            return DeployStatus::Conflict {
+                cause: format!("Failed to read symlink. {}", e),
+            }
+        }
+    };
+    if !to.as_ref().exists() {
+        return DeployStatus::Conflict {
+            cause: format!("Symlink is broken. {}", abs_to_link.to_string_lossy()),
+        };
+    }
+    // relative and absolute links to the same path are equivalent
+    let same = match (fs::canonicalize(from), fs::canonicalize(&to)) {
+        (Ok(from), Ok(to)) => from == to,
+        _ => false,
+    };
+    if !same {
         return DeployStatus::Conflict {
             cause: format!(
                 "Symlink to different path. {}",
@@ -77,6 +106,70 @@ where
     DeployStatus::Deployed
 }
 
+/// Status of the target deployed by hard link.
+/// A different file at the destination, e.g. written by an editor replacing the file, is a conflict.
+pub fn get_hardlink_status<P, Q>(from: P, to: Q) -> DeployStatus
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if !from.exists() {
+        return DeployStatus::UnManaged;
+    }
+    if to.symlink_metadata().is_err() {
+        return DeployStatus::UnDeployed;
+    }
+    if to.is_symlink() || !to.is_file() {
+        return DeployStatus::Conflict {
+            cause: format!("Other file exists. {}", to.to_string_lossy()),
+        };
+    }
+
+    match fs::is_same_file(from, to) {
+        Ok(true) => DeployStatus::Deployed,
+        Ok(false) => DeployStatus::Conflict {
+            cause: format!(
+                "Hard link is broken. {} is a different file.",
+                to.to_string_lossy()
+            ),
+        },
+        Err(e) => DeployStatus::Conflict {
+            cause: format!("Failed to read. {}", e),
+        },
+    }
+}
+
+/// Status of the target deployed by copy. `record` is the record of the copy if deployed.
+pub fn get_copy_status<P, Q>(from: P, to: Q, record: Option<&CopyRecord>) -> DeployStatus
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if !from.exists() {
+        return DeployStatus::UnManaged;
+    }
+    if to.symlink_metadata().is_err() {
+        return DeployStatus::UnDeployed;
+    }
+    let Some(record) = record.filter(|_| !to.is_symlink()) else {
+        return DeployStatus::Conflict {
+            cause: format!("Other file exists. {}", to.to_string_lossy()),
+        };
+    };
+
+    // changes of the copy take precedence over updates of the repository
+    match (fs::content_hash(from), fs::content_hash(to)) {
+        (Ok(_), Ok(to)) if to != record.sha256 => DeployStatus::Modified,
+        (Ok(from), Ok(_)) if from != record.sha256 => DeployStatus::Outdated,
+        (Ok(_), Ok(_)) => DeployStatus::Deployed,
+        (Err(e), _) | (_, Err(e)) => DeployStatus::Conflict {
+            cause: format!("Failed to read. {}", e),
+        },
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use pretty_assertions::{assert_eq, assert_ne};
@@ -90,6 +183,10 @@ mod tests {
     #[case(DeployStatus::UnDeployed, DeployStatus::UnDeployed, true)]
     #[case(DeployStatus::Deployed, DeployStatus::Deployed, true)]
     #[case(DeployStatus::UnManaged, DeployStatus::UnManaged, true)]
+    #[case(DeployStatus::Modified, DeployStatus::Modified, true)]
+    #[case(DeployStatus::Modified, DeployStatus::Deployed, false)]
+    #[case(DeployStatus::Outdated, DeployStatus::Outdated, true)]
+    #[case(DeployStatus::Outdated, DeployStatus::Modified, false)]
     #[case(
         DeployStatus::Conflict {
             cause: "cause".to_string()
@@ -154,6 +251,10 @@ mod tests {
     #[case(DeployStatus::UnDeployed, DeployStatus::UnDeployed, true)]
     #[case(DeployStatus::Deployed, DeployStatus::Deployed, true)]
     #[case(DeployStatus::UnManaged, DeployStatus::UnManaged, true)]
+    #[case(DeployStatus::Modified, DeployStatus::Modified, true)]
+    #[case(DeployStatus::Modified, DeployStatus::Deployed, false)]
+    #[case(DeployStatus::Outdated, DeployStatus::Outdated, true)]
+    #[case(DeployStatus::Outdated, DeployStatus::Modified, false)]
     #[case(
         DeployStatus::Conflict {
             cause: "cause".to_string()
@@ -224,6 +325,8 @@ mod tests {
     #[case(DeployStatus::UnDeployed, "UnDeployed")]
     #[case(DeployStatus::Deployed, "Deployed")]
     #[case(DeployStatus::UnManaged, "UnManaged")]
+    #[case(DeployStatus::Modified, "Modified")]
+    #[case(DeployStatus::Outdated, "Outdated")]
     #[case(
         DeployStatus::Conflict {
             cause: "cause".to_string()
@@ -233,4 +336,92 @@ mod tests {
     fn test_deploy_status_display(#[case] status: DeployStatus, #[case] expected: &str) {
         assert_eq!(status.to_string(), expected);
     }
+
+    #[test]
+    fn test_get_copy_status() -> anyhow::Result<()> {
+        use assert_fs::prelude::*;
+
+        let temp = assert_fs::TempDir::new()?;
+        let from = temp.child("repo/.profile");
+        from.write_str("export A=1\n")?;
+        let to = temp.child("home/.profile");
+        let record = CopyRecord {
+            from: from.to_path_buf(),
+            sha256: fs::content_hash(from.path())?,
+        };
+
+        assert_eq!(
+            get_copy_status(temp.child("repo/missing").path(), to.path(), None),
+            DeployStatus::UnManaged
+        );
+        assert_eq!(
+            get_copy_status(from.path(), to.path(), None),
+            DeployStatus::UnDeployed
+        );
+        to.write_str("export A=1\n")?;
+        assert_eq!(
+            get_copy_status(from.path(), to.path(), None),
+            DeployStatus::Conflict {
+                cause: String::new()
+            }
+        );
+        assert_eq!(
+            get_copy_status(from.path(), to.path(), Some(&record)),
+            DeployStatus::Deployed
+        );
+        // the copy is changed
+        to.write_str("export A=2\n")?;
+        assert_eq!(
+            get_copy_status(from.path(), to.path(), Some(&record)),
+            DeployStatus::Modified
+        );
+        // the repository is updated
+        to.write_str("export A=1\n")?;
+        from.write_str("export A=2\n")?;
+        assert_eq!(
+            get_copy_status(from.path(), to.path(), Some(&record)),
+            DeployStatus::Outdated
+        );
+        // both are changed
+        to.write_str("export A=3\n")?;
+        assert_eq!(
+            get_copy_status(from.path(), to.path(), Some(&record)),
+            DeployStatus::Modified
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_hardlink_status() -> anyhow::Result<()> {
+        use assert_fs::prelude::*;
+
+        let temp = assert_fs::TempDir::new()?;
+        let from = temp.child("repo/.profile");
+        from.write_str("export A=1\n")?;
+        let to = temp.child("home/.profile");
+        temp.child("home").create_dir_all()?;
+
+        assert_eq!(
+            get_hardlink_status(temp.child("repo/missing").path(), to.path()),
+            DeployStatus::UnManaged
+        );
+        assert_eq!(
+            get_hardlink_status(from.path(), to.path()),
+            DeployStatus::UnDeployed
+        );
+        fs::hardlink(from.path(), to.path())?;
+        assert_eq!(
+            get_hardlink_status(from.path(), to.path()),
+            DeployStatus::Deployed
+        );
+
+        // an editor writes a new file
+        std::fs::remove_file(to.path())?;
+        to.write_str("export A=1\n")?;
+        let DeployStatus::Conflict { cause } = get_hardlink_status(from.path(), to.path()) else {
+            panic!("not a conflict");
+        };
+        assert!(cause.starts_with("Hard link is broken."));
+        Ok(())
+    }
 }
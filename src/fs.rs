@@ -1,7 +1,8 @@
 //! File system utilities.
-use anyhow::Result;
+use anyhow::{bail, Context as _, Result};
 use dunce::simplified;
 use path_abs::PathAbs;
+use sha2::{Digest, Sha256};
 use std::path::Path;
 use std::path::PathBuf;
 
@@ -13,13 +14,13 @@ cfg_if::cfg_if! {
     }
 }
 
-#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[cfg(any(all(unix, not(target_os = "macos")), target_os = "windows"))]
 use anyhow::anyhow;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(unix, not(target_os = "macos")))]
 use std::fs;
 
-#[cfg(target_os = "linux")]
+#[cfg(all(unix, not(target_os = "macos")))]
 use trash::Error;
 
 pub fn absolutize<P>(path: P) -> Result<PathBuf>
@@ -58,12 +59,98 @@ where
     }
 }
 
+/// Symbolic link to `from` by the path relative to the directory of `to`.
+pub fn symlink_relative<P, Q>(from: P, to: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+    let dir = real_parent(to)?;
+    let relative = pathdiff::diff_paths(absolutize(from)?, &dir)
+        .with_context(|| format!("Can not make {:?} relative to {:?}.", from, dir))?;
+
+    #[cfg(target_family = "unix")]
+    {
+        std::os::unix::fs::symlink(relative, to)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "windows")]
+    {
+        if from.is_dir() {
+            std::os::windows::fs::symlink_dir(relative, to)?;
+        } else {
+            std::os::windows::fs::symlink_file(relative, to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Absolute path the symbolic link points to. A relative link is resolved from the directory of the link.
+pub fn link_target<P>(link: P) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let link = link.as_ref();
+    let target = std::fs::read_link(link)?;
+    if target.is_absolute() {
+        return absolutize(target);
+    }
+    absolutize(real_parent(link)?.join(target))
+}
+
+/// Parent directory of the path with symbolic links resolved,
+/// from which the OS resolves a relative link.
+fn real_parent(path: &Path) -> Result<PathBuf> {
+    let dir = absolutize(path)?
+        .parent()
+        .map(Path::to_path_buf)
+        .with_context(|| format!("{:?} has no parent directory.", path))?;
+    canonicalize(&dir)
+}
+
+/// Absolute path with symbolic links resolved.
+pub fn canonicalize<P>(path: P) -> Result<PathBuf>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    dunce::canonicalize(path).with_context(|| format!("Failed to resolve {:?}", path))
+}
+
+/// Hard link `to` to the file `from`. Directories can not be hard linked.
+pub fn hardlink<P, Q>(from: P, to: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let from = from.as_ref();
+    if from.is_dir() {
+        bail!(
+            "Can not deploy. {:?} is a directory and can not be hard linked.",
+            from
+        );
+    }
+    std::fs::hard_link(from, to)?;
+    Ok(())
+}
+
+/// Whether the paths are the same file, by device and inode (volume and file index on Windows).
+pub fn is_same_file<P, Q>(a: P, b: Q) -> Result<bool>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    Ok(same_file::is_same_file(a, b)?)
+}
+
 pub fn remove<P>(path: P) -> Result<()>
 where
     P: AsRef<Path>,
 {
     cfg_if::cfg_if! {
-        if #[cfg(target_os = "linux")] {
+        if #[cfg(all(unix, not(target_os = "macos")))] {
             match delete(&path) {
                 Ok(_) => Ok(()),
                 Err(Error::FileSystem { .. }) => {
@@ -83,3 +170,73 @@ where
         }
     }
 }
+
+/// Copy the file, or the directory recursively.
+pub fn copy<P, Q>(from: P, to: Q) -> Result<()>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if from.is_dir() {
+        std::fs::create_dir_all(to)?;
+        for entry in std::fs::read_dir(from)? {
+            let entry = entry?;
+            copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    } else {
+        std::fs::copy(from, to)?;
+    }
+    Ok(())
+}
+
+/// SHA-256 digest of the content of the file, or the directory recursively, in hex.
+/// A directory is hashed with the names and digests of its entries in order of name.
+pub fn content_hash<P>(path: P) -> Result<String>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let digest = if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        let mut hasher = Sha256::new();
+        for entry in entries {
+            hasher.update(entry.file_name().to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(content_hash(entry.path())?.as_bytes());
+            hasher.update([b'\n']);
+        }
+        hasher.finalize()
+    } else {
+        Sha256::digest(std::fs::read(path)?)
+    };
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Remove the file, or the directory recursively, without moving it to the trash.
+/// A symbolic link is removed, not the directory it points to.
+pub fn remove_all<P>(path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    if !path.is_symlink() && path.is_dir() {
+        std::fs::remove_dir_all(path)?;
+    } else if path.is_symlink() && path.is_dir() && cfg!(target_os = "windows") {
+        // directory symbolic links are directories on Windows
+        std::fs::remove_dir(path)?;
+    } else {
+        std::fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Whether the path is a directory, not a symbolic link to a directory.
+pub fn is_real_dir<P>(path: P) -> bool
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    path.is_dir() && !path.is_symlink()
+}
@@ -0,0 +1,314 @@
+//! Git operations.
+//!
+//! Repositories are cloned and updated to the ref they are pinned to.
+//! - branch: checked out and updated by fast-forward.
+//! - tag, rev: checked out as detached HEAD.
+//! - not pinned: `git pull` on the checked out branch.
+use crate::config::Pin;
+use anyhow::{bail, Context as _, Result};
+use itertools::Itertools;
+use std::ffi::OsStr;
+use std::path::Path;
+use std::process::Command;
+
+/// Clone the repository and check out the pinned ref.
+pub fn clone(url: &str, path: &Path, pin: Option<Pin>, quiet: bool, verbose: bool) -> Result<()> {
+    let mut args = vec![OsStr::new("clone")];
+    if let Some(Pin::Branch(name) | Pin::Tag(name)) = pin {
+        args.extend([OsStr::new("--branch"), OsStr::new(name)]);
+    }
+    args.extend([OsStr::new(url), path.as_os_str()]);
+    if verbose {
+        args.push(OsStr::new("-v"));
+    }
+    run(None, &args, quiet)?;
+
+    if let Some(Pin::Rev(rev)) = pin {
+        checkout_detach(path, rev, quiet)?;
+    }
+    Ok(())
+}
+
+/// Fetch and move the working copy to the pinned ref.
+pub fn update(path: &Path, pin: Option<Pin>, quiet: bool) -> Result<()> {
+    match pin {
+        None => run(Some(path), &["pull"], quiet),
+        Some(Pin::Branch(branch)) => {
+            run(Some(path), &["fetch", "origin"], quiet)?;
+            if current_branch(path)?.as_deref() != Some(branch) {
+                run(Some(path), &["checkout", branch], quiet)?;
+            }
+            run(
+                Some(path),
+                &["merge", "--ff-only", &format!("origin/{}", branch)],
+                quiet,
+            )
+        }
+        Some(Pin::Tag(tag)) => {
+            run(Some(path), &["fetch", "--tags", "origin"], quiet)?;
+            checkout_detach(path, &format!("refs/tags/{}", tag), quiet)
+        }
+        Some(Pin::Rev(rev)) => {
+            run(Some(path), &["fetch", "origin"], quiet)?;
+            checkout_detach(path, rev, quiet)
+        }
+    }
+}
+
+/// Describe how the working copy diverges from the pinned ref.
+/// Returns `None` if the working copy is at the pinned ref.
+/// Remote branches are compared as of the last fetch.
+pub fn divergence(path: &Path, pin: Pin) -> Result<Option<String>> {
+    match pin {
+        Pin::Branch(branch) => {
+            match current_branch(path)? {
+                None => return Ok(Some(format!("HEAD is detached, expected {}", pin))),
+                Some(current) if current != branch => {
+                    return Ok(Some(format!("on branch {}, expected {}", current, pin)))
+                }
+                Some(_) => {}
+            }
+            let upstream = format!("origin/{}", branch);
+            let counts = query(
+                path,
+                &[
+                    "rev-list",
+                    "--left-right",
+                    "--count",
+                    &format!("HEAD...{}", upstream),
+                ],
+            )?;
+            let (ahead, behind) = counts
+                .split_whitespace()
+                .map(str::parse::<usize>)
+                .collect_tuple()
+                .with_context(|| format!("Unexpected output of git rev-list: {}", counts))?;
+            let (ahead, behind) = (ahead?, behind?);
+            Ok((ahead > 0 || behind > 0)
+                .then(|| format!("ahead {}, behind {} of {}", ahead, behind, upstream)))
+        }
+        Pin::Tag(tag) => compare_head(path, pin, &format!("refs/tags/{}", tag)),
+        Pin::Rev(rev) => compare_head(path, pin, rev),
+    }
+}
+
+fn compare_head(path: &Path, pin: Pin, target: &str) -> Result<Option<String>> {
+    let head = query(path, &["rev-parse", "HEAD"])?;
+    let expected = query(path, &["rev-parse", &format!("{}^{{commit}}", target)])?;
+    Ok((head != expected).then(|| {
+        format!(
+            "HEAD is at {}, expected {} ({})",
+            short(&head),
+            pin,
+            short(&expected)
+        )
+    }))
+}
+
+fn short(commit: &str) -> &str {
+    &commit[..commit.len().min(7)]
+}
+
+/// Name of the checked out branch. `None` if HEAD is detached.
+fn current_branch(path: &Path) -> Result<Option<String>> {
+    let mut git = Command::new("git");
+    git.args(["symbolic-ref", "--short", "-q", "HEAD"])
+        .current_dir(path);
+    let output = output(&mut git)?;
+    // exit code 1 means detached HEAD
+    if output.status.code() == Some(1) {
+        return Ok(None);
+    }
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(Some(
+        String::from_utf8_lossy(&output.stdout).trim().to_string(),
+    ))
+}
+
+fn checkout_detach(path: &Path, target: &str, quiet: bool) -> Result<()> {
+    run(
+        Some(path),
+        &[
+            "-c",
+            "advice.detachedHead=false",
+            "checkout",
+            "--detach",
+            target,
+        ],
+        quiet,
+    )
+}
+
+fn output(git: &mut Command) -> Result<std::process::Output> {
+    git.output().with_context(|| {
+        format!(
+            "Failed to execute git {:}",
+            git.get_args().map(|s| s.to_string_lossy()).join(" ")
+        )
+    })
+}
+
+/// Run git command. stderr is printed unless quiet, and stdout is logged.
+fn run<S>(dir: Option<&Path>, args: &[S], quiet: bool) -> Result<()>
+where
+    S: AsRef<OsStr>,
+{
+    let mut git = Command::new("git");
+    git.args(args);
+    if let Some(dir) = dir {
+        git.current_dir(dir);
+    }
+    if quiet {
+        git.arg("-q");
+    }
+
+    let output = output(&mut git)?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let stderr = stderr.trim_end();
+    if !output.status.success() {
+        bail!("{}", stderr);
+    }
+
+    if !quiet && !stderr.is_empty() {
+        println!("{}", stderr);
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stdout = stdout.trim_end();
+    if !stdout.is_empty() {
+        log::info!("{}", stdout);
+    }
+    Ok(())
+}
+
+/// Run git command and return stdout.
+fn query(path: &Path, args: &[&str]) -> Result<String> {
+    let mut git = Command::new("git");
+    git.args(args).current_dir(path);
+    let output = output(&mut git)?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim_end());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn git(path: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(["-c", "user.name=rrcm", "-c", "user.email=rrcm@example.com"])
+            .args(args)
+            .current_dir(path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    fn commit(path: &Path, file: &str) -> String {
+        std::fs::write(path.join(file), file).unwrap();
+        git(path, &["add", file]);
+        git(path, &["commit", "-q", "-m", file]);
+        git(path, &["rev-parse", "HEAD"])
+    }
+
+    /// Create an upstream repository with branches `main` and `dev`, and tag `v1`.
+    fn upstream(path: &Path) -> (String, String) {
+        std::fs::create_dir(path).unwrap();
+        git(path, &["init", "-q", "-b", "main"]);
+        let first = commit(path, "first");
+        git(path, &["tag", "v1"]);
+        let second = commit(path, "second");
+        git(path, &["branch", "dev", &first]);
+        (first, second)
+    }
+
+    #[test]
+    fn test_clone_update_branch() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let origin = temp.path().join("origin");
+        let (first, _) = upstream(&origin);
+        let local = temp.path().join("local");
+
+        clone(
+            &origin.to_string_lossy(),
+            &local,
+            Some(Pin::Branch("dev")),
+            true,
+            false,
+        )?;
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), first);
+        assert_eq!(divergence(&local, Pin::Branch("dev"))?, None);
+        assert_eq!(
+            divergence(&local, Pin::Branch("main"))?,
+            Some("on branch dev, expected branch main".to_string())
+        );
+
+        // fast-forward after upstream moves
+        git(&origin, &["checkout", "-q", "dev"]);
+        let third = commit(&origin, "third");
+        update(&local, Some(Pin::Branch("dev")), true)?;
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), third);
+
+        // switch to another branch
+        update(&local, Some(Pin::Branch("main")), true)?;
+        assert_eq!(current_branch(&local)?, Some("main".to_string()));
+
+        git(&local, &["reset", "-q", "--hard", "HEAD~1"]);
+        assert_eq!(
+            divergence(&local, Pin::Branch("main"))?,
+            Some("ahead 0, behind 1 of origin/main".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_clone_update_detached() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let origin = temp.path().join("origin");
+        let (first, second) = upstream(&origin);
+        let local = temp.path().join("local");
+
+        clone(
+            &origin.to_string_lossy(),
+            &local,
+            Some(Pin::Tag("v1")),
+            true,
+            false,
+        )?;
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), first);
+        assert_eq!(current_branch(&local)?, None);
+        assert_eq!(divergence(&local, Pin::Tag("v1"))?, None);
+
+        update(&local, Some(Pin::Rev(&second)), true)?;
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), second);
+        assert_eq!(
+            divergence(&local, Pin::Tag("v1"))?,
+            Some(format!(
+                "HEAD is at {}, expected tag v1 ({})",
+                short(&second),
+                short(&first)
+            ))
+        );
+        assert_eq!(
+            divergence(&local, Pin::Branch("main"))?,
+            Some("HEAD is detached, expected branch main".to_string())
+        );
+
+        let other = temp.path().join("other");
+        clone(
+            &origin.to_string_lossy(),
+            &other,
+            Some(Pin::Rev(&first)),
+            true,
+            false,
+        )?;
+        assert_eq!(git(&other, &["rev-parse", "HEAD"]), first);
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+//! Host utilities.
+//!
+//! Host rules in the configuration are matched against the host name
+//! of the running machine with a simple glob pattern.
+//! - `*` matches any sequence of characters
+//! - `?` matches any single character
+//!
+//! Matching is case insensitive.
+//! A pattern matches either the full host name or the short host name
+//! (the part before the first `.`).
+use std::sync::OnceLock;
+
+/// Get host name of the running machine.
+pub fn hostname() -> String {
+    gethostname::gethostname().to_string_lossy().to_string()
+}
+
+/// Check if the host name matches the pattern.
+pub fn matches(pattern: &str, hostname: &str) -> bool {
+    let pattern = pattern.to_lowercase().chars().collect::<Vec<_>>();
+    let hostname = hostname.to_lowercase();
+    let short = hostname.split('.').next().unwrap_or_default();
+
+    glob_match(&pattern, &hostname.chars().collect::<Vec<_>>())
+        || glob_match(&pattern, &short.chars().collect::<Vec<_>>())
+}
+
+/// Check if running on WSL (Windows Subsystem for Linux).
+pub fn is_wsl() -> bool {
+    static IS_WSL: OnceLock<bool> = OnceLock::new();
+    *IS_WSL.get_or_init(|| {
+        cfg!(target_os = "linux")
+            && is_wsl_with(
+                std::fs::read_to_string("/proc/version").ok().as_deref(),
+                std::env::var("WSL_DISTRO_NAME").ok().as_deref(),
+            )
+    })
+}
+
+/// Check if running on WSL from the content of `/proc/version`
+/// and the value of `WSL_DISTRO_NAME` environment variable.
+pub fn is_wsl_with(proc_version: Option<&str>, distro_name: Option<&str>) -> bool {
+    if distro_name.is_some_and(|name| !name.is_empty()) {
+        return true;
+    }
+    proc_version.is_some_and(|version| {
+        let version = version.to_lowercase();
+        version.contains("microsoft") || version.contains("wsl")
+    })
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // position of last `*` in pattern and the text position it matched from
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => {
+                let Some((star_p, star_t)) = backtrack else {
+                    return false;
+                };
+                p = star_p + 1;
+                t = star_t + 1;
+                backtrack = Some((star_p, star_t + 1));
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("build01", "build01", true)]
+    #[case("build*", "build01", true)]
+    #[case("build??", "build01", true)]
+    #[case("build?", "build01", false)]
+    #[case("*", "laptop", true)]
+    #[case("*01", "build01", true)]
+    #[case("b*d*1", "build01", true)]
+    #[case("BUILD*", "build01", true)]
+    #[case("build*", "laptop", false)]
+    #[case("build01", "build01.example.com", true)]
+    #[case("*.example.com", "build01.example.com", true)]
+    #[case("*.example.org", "build01.example.com", false)]
+    #[case("", "build01", false)]
+    fn test_matches(#[case] pattern: &str, #[case] hostname: &str, #[case] expected: bool) {
+        assert_eq!(matches(pattern, hostname), expected);
+    }
+
+    #[rstest]
+    #[case(None, None, false)]
+    #[case(
+        Some("Linux version 6.5.0-14-generic (buildd@lcy02-amd64-031) (gcc 12.3.0)"),
+        None,
+        false
+    )]
+    #[case(
+        Some("Linux version 4.4.0-19041-Microsoft (Microsoft@Microsoft.com) (gcc version 5.4.0)"),
+        None,
+        true
+    )]
+    #[case(
+        Some("Linux version 5.15.133.1-microsoft-standard-WSL2 (root@1c602f52c2e4)"),
+        None,
+        true
+    )]
+    #[case(None, Some("Ubuntu"), true)]
+    #[case(None, Some(""), false)]
+    fn test_is_wsl_with(
+        #[case] proc_version: Option<&str>,
+        #[case] distro_name: Option<&str>,
+        #[case] expected: bool,
+    ) {
+        assert_eq!(is_wsl_with(proc_version, distro_name), expected);
+    }
+}
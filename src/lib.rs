@@ -1,7 +1,14 @@
 pub mod config;
 mod deploy_status;
 mod fs;
+mod git;
+mod host;
 mod path;
+mod state;
 mod subcommand;
+mod when;
 
-pub use subcommand::{deploy, status, undeploy, update};
+pub use subcommand::{
+    config_path, convert, deploy, migrate, pull, repo_add, repo_list, repo_remove, show, status,
+    undeploy, update, validate,
+};
@@ -40,8 +40,26 @@
 //! configuration file path:
 //! - Unix: $HOME/.config/rrcm/config.yaml
 //! - Win: %PROFILE%\AppData\Roaming\rrcm\config.yaml
+//!
+//! configuration files are layered in the following order. later files take precedence.
+//! 1. system: `rrcm/config.yaml` in `$XDG_CONFIG_DIRS` (default `/etc/xdg`, except on Windows and macOS)
+//! 2. user: the configuration file path above
+//! 3. project: `.rrcm.yaml` in the current directory or the nearest parent directory
+//!
+//! every key is optional in these files, e.g. a project file can have only `repos`.
+//! `vars` are merged, `dotfiles` and `relative_links` are taken from the last file setting them, and repositories with the same name are replaced.
+//! commands editing the configuration, such as `rrcm repo add`, edit the last file.
+//! with `--config` or `RRCM_CONFIG`, only the given file is used.
+//! `rrcm config path` prints the files in order of loading.
+//!
+//! TOML (`config.toml`) and JSON (`config.json`) are also supported.
+//! The format is selected by the file extension, also for `--config` and included files.
+//! If several exist in the configuration directory, `config.yaml`, `config.toml` and `config.json` are used in this order.
 //! ```yaml
 //! ---
+//! # configuration file version. older files can be updated by `rrcm config migrate`.
+//! version: 1
+//!
 //! # dotfiles repositroy download directory
 //! dotfiles:
 //!   windows: "%USERPROFILE%\\dotfiles"
@@ -57,6 +75,18 @@
 //!     # git repository url
 //!     url: 'git@github:example/example1'
 //!
+//!     # optional. pin the repository to a branch, tag or commit. only one of them can be set.
+//!     # branch is updated by fast-forward, tag and rev are checked out as detached HEAD.
+//!     # `rrcm status` shows when the working copy diverges from the pin.
+//!     branch: main
+//!     # tag: v1.0.0
+//!     # rev: 0123abc
+//!
+//!     # optional. directory used instead of `dotfiles` for this repository.
+//!     # the repository is cloned to `<dir>/<name>`. `dotfiles` is used on OS not defined.
+//!     # dir:
+//!     #   linux: "/mnt/secure"
+//!
 //!     # deploy configuration
 //!     deploy:
 //!
@@ -70,6 +100,10 @@
 //!         mac: "${HOME}"
 //!         linux: "${HOME}"
 //!
+//!       # Example: unix applies to Linux, macOS and BSDs
+//!       .ssh:
+//!         unix: "${HOME}/.ssh"
+//!
 //!       # Example: deploy .config directory to XDG_CONFIG_HOME or %USERPROFILE%\AppData\Roaming
 //!       .config:
 //!         windows: "%FOLDERID_RoamingAppData%"
@@ -89,6 +123,17 @@
 //! ```
 //!
 //! ### Deployment destination
+//! The destination of the running OS is looked up in the following order.
+//! If none is defined, it will not be deployed.
+//! - Windows: `windows`, `default`
+//! - macOS: `mac`, `unix`, `default`
+//! - Linux: `linux`, `unix`, `default`
+//! - WSL: `wsl`, `linux`, `unix`, `default`
+//! - FreeBSD: `freebsd`, `unix`, `default`
+//! - OpenBSD: `openbsd`, `unix`, `default`
+//!
+//! WSL is detected by `WSL_DISTRO_NAME` environment variable or `/proc/version`.
+//!
 //! Environment variables can be used in deployment destination.
 //!
 //! Format
@@ -97,7 +142,7 @@
 //!
 //! The following special variables are available.
 //! - Unix [XDG base directory](https://specifications.freedesktop.org/basedir-spec/basedir-spec-latest.html)
-//!     if the environment variable is not set, the default value is used.
+//!   if the environment variable is not set, the default value is used.
 //!     - ${XDG_CONFIG_HOME}
 //!     - ${XDG_DATA_HOME}
 //! - Windows [Known Folder ID](https://docs.microsoft.com/en-us/windows/win32/shell/knownfolderid)
@@ -106,6 +151,190 @@
 //!     - %FOLDERID_Documents%
 //!     - %FOLDERID_Desktop%
 //!
+//! ### Deploy mode
+//! Files are deployed by symbolic links. `mode: copy` deploys copies instead,
+//! for applications which replace symbolic links, or Windows without administrator.
+//! the digests of the copies are recorded in `.rrcm-state.yaml` in the dotfiles directory.
+//! `rrcm status` shows `Modified` if a copy is changed after deployment,
+//! and `Outdated` if the file is updated in the repository.
+//! `rrcm deploy` copies again files updated in the repository,
+//! but does not overwrite copies changed after deployment without `--force`.
+//!
+//! `mode: hardlink` deploys hard links, for applications which do not follow symbolic links, e.g. sshd with `StrictModes`.
+//! only files can be hard linked, and the destination must be on the same file system as the dotfiles directory.
+//! if an editor replaces the file with a new one, the link is broken and `rrcm status` shows `Conflict`.
+//! ```yaml
+//!     deploy:
+//!       .config:
+//!         default: "${XDG_CONFIG_HOME}"
+//!         mode: copy
+//!       .ssh:
+//!         default: "${HOME}/.ssh"
+//!         mode: hardlink
+//! ```
+//!
+//! ### Relative links
+//! symbolic links point to the absolute path in the dotfiles directory by default.
+//! with `relative_links: true`, links are relative to the destination directory,
+//! and keep working when the home directory is moved or mounted at another path, e.g. in a container.
+//! it can also be set for each deploy target. links of either kind are shown as `Deployed` by `rrcm status`.
+//! ```yaml
+//! relative_links: true
+//! repos:
+//!   - name: example1
+//!     url: 'git@github:example/example1'
+//!     deploy:
+//!       home:
+//!         default: "${HOME}"
+//!       system:
+//!         default: /etc/example
+//!         relative_links: false
+//! ```
+//!
+//! ### Recursive deployment
+//! entries of a deploy source directory are linked as they are,
+//! so a directory already existing at the destination, e.g. `~/.config/nvim`, is a conflict.
+//! with `recursive: true`, such directories are descended into and only the files in them are linked.
+//! as GNU Stow, a directory not existing at the destination is linked as a whole,
+//! and it is unfolded into a directory of links when another repository deploys into it.
+//! only links into the directories of the repositories, including those moved by `dir`, are unfolded,
+//! and other links are conflicts.
+//! `rrcm undeploy` removes the directories created by rrcm under the deploy targets of the repository when they become empty,
+//! or folds them back into a link when all the entries link to the same directory of a repository.
+//! the created directories are recorded in `.rrcm-state.yaml`. with `mode: hardlink`, directories are created and files are hard linked.
+//! ```yaml
+//!     deploy:
+//!       .config:
+//!         default: "${XDG_CONFIG_HOME}"
+//!         recursive: true
+//! ```
+//!
+//! ### Variables
+//! `vars` defines variables usable in `dotfiles` and every deployment destination,
+//! in the same format as environment variables.
+//! Values can refer to environment variables and other variables.
+//! Variables take precedence over environment variables of the same name.
+//! ```yaml
+//! vars:
+//!   WORK_ROOT: "${HOME}/work"
+//!   WORK_CONFIG: "${WORK_ROOT}/config"
+//! ```
+//!
+//! ### Host specific deployment
+//! `hosts` overrides deploy targets on machines whose host name matches the pattern.
+//! Deploy targets of matching patterns are added, or replace the target with the same name.
+//! A target set to `~` is not deployed on matching hosts.
+//! If multiple patterns match, they are applied in order and later ones take precedence.
+//! Patterns support `*` and `?`, are case insensitive,
+//! and match either the full host name or the short host name.
+//! ```yaml
+//! repos:
+//!   - name: example1
+//!     url: 'git@github:example/example1'
+//!     deploy:
+//!       home:
+//!         windows: "%USERPROFILE%"
+//!         mac: "${HOME}"
+//!         linux: "${HOME}"
+//!       home-laptop:
+//!         linux: "${HOME}"
+//!     hosts:
+//!       # on build01, build02, ... deploy home-build instead of home-laptop
+//!       "build*":
+//!         home-laptop: ~
+//!         home-build:
+//!           linux: "${HOME}"
+//! ```
+//! `rrcm status` shows the host rules applied.
+//!
+//! ### Conditions
+//! `when` skips a repository or a deploy target unless the condition is true on the running machine.
+//! Conditions are evaluated before deployment, and `rrcm status` shows skipped ones with the reason.
+//! - `env(NAME)`: environment variable `NAME` is set
+//! - `env(NAME) == value`, `env(NAME) != value`: environment variable `NAME` equals to `value`
+//! - `command(name)`: executable command `name` is found on `PATH`
+//! - `exists(path)`: file or directory exists. variables can be used.
+//! - `hostname(pattern)`: host name matches the pattern, same as `hosts`
+//! - `os(name)`: running on the OS. `name` is a key of deployment destination, e.g. `linux`, `unix` or `wsl`
+//! - `arch(name)`: CPU architecture, e.g. `x86_64` or `aarch64`
+//! - `true`, `false`
+//!
+//! Conditions are combined with `!`, `&&`, `||` (or `not`, `and`, `or`) and parentheses.
+//! Arguments containing spaces or symbols are quoted with `"` or `'`.
+//! ```yaml
+//! repos:
+//!   - name: work
+//!     url: 'git@github:example/work'
+//!     when: env(WORK_MACHINE) == 1 && !os(windows)
+//!     deploy:
+//!       home:
+//!         unix: "${HOME}"
+//!       nvim:
+//!         unix: "${XDG_CONFIG_HOME}/nvim"
+//!         when: command(nvim)
+//! ```
+//!
+//! ### Local directory
+//! A repository with `path` instead of `url` is a plain local directory, e.g. machine specific overlays.
+//! It is deployed in the same way, but never cloned or pulled by `rrcm update`.
+//! `path` is absolute, or relative to `dotfiles`, and variables can be used.
+//! `rrcm status` shows it as a local directory.
+//! ```yaml
+//! repos:
+//!   - name: overlay
+//!     path: "${HOME}/overlay"
+//!     deploy:
+//!       home:
+//!         unix: "${HOME}"
+//! ```
+//!
+//! ### Include
+//! `include` merges repositories of other config files.
+//! Only `repos` and nested `include` of included files are used.
+//! Relative paths are resolved from the directory of the including file,
+//! and environment variables can be used.
+//! Repository names must be unique across all files.
+//! ```yaml
+//! include:
+//!   - team/config.yaml
+//!   - "${HOME}/work/rrcm.yaml"
+//! ```
+//!
+//! ### Local override
+//! `config.local.yaml` next to the configuration file is merged into it, for changes only on the machine,
+//! e.g. when `config.yaml` is shared by `rrcm init <url>`.
+//! mappings are merged by key, and `~` removes the key. repositories are matched by `name`,
+//! and deploy targets by key. `dotfiles` and each deploy target are replaced as a whole.
+//! `repos` can also be a mapping from the name, where `~` removes the repository.
+//! `config.local.toml` or `config.local.json` is read as well, whatever the format of the configuration file is,
+//! but only one of them may exist.
+//! ```yaml
+//! # config.local.yaml
+//! dotfiles:
+//!   default: /work/dotfiles
+//! repos:
+//!   - name: example1
+//!     deploy:
+//!       .config: ~ # do not deploy .config on this machine
+//!   - name: machine
+//!     path: /opt/machine-dotfiles
+//!     deploy:
+//!       home:
+//!         default: ${HOME}
+//! ```
+//! `rrcm config show --resolved` prints the merged configuration.
+//!
+//! ### Environment variables
+//! Environment variables override the configuration, and command line flags override environment variables.
+//! Empty values are ignored.
+//! - `RRCM_CONFIG`: configuration file path. `--config` takes precedence.
+//! - `RRCM_DOTFILES`: dotfiles repository download directory, replacing `dotfiles` on every OS.
+//! - `RRCM_REPOS`: comma separated repository names for `status`, `deploy`, `undeploy` and `update`.
+//!   a repository name given as the argument takes precedence.
+//! ```sh
+//! RRCM_CONFIG=/ci/rrcm.yaml RRCM_DOTFILES=/work/dotfiles RRCM_REPOS=example1,example2 rrcm update
+//! ```
+//!
 //! ## Install
 //! ### Cargo
 //! ```sh
@@ -118,10 +347,36 @@
 //! rrcm init
 //! # or initialize config file from http
 //! rrcm init <url>
+//! # or from a local file, file:// url, or stdin
+//! rrcm init /media/usb/config.yaml
+//! rrcm init file:///media/usb/config.yaml
+//! secret-tool lookup rrcm config | rrcm init -
 //! ```
 //! I recommend using gist like this.
 //! [my config.yaml](https://gist.github.com/mizuki0629/1f7e73703b09551610b18392e375bd73)
 //!
+//! the file is written only if it is parsed as a configuration file in the format of the destination.
+//! it can also be verified before it is written, and nothing is written if the verification fails.
+//! signatures of local files are read next to the file.
+//! - `--sha256 <HEX>`: SHA-256 digest of the file
+//! - `--minisign-key <KEY>`: minisign signature `<url>.minisig`, made by `minisign -S`. the key is base64 or a file path.
+//! - `--ssh-allowed-signers <FILE> --ssh-identity <IDENTITY>`: SSH signature `<url>.sig`, made by `ssh-keygen -Y sign -n file`
+//!
+//! the url or path, and the digest of the file are recorded in `config.source.yaml`, except for stdin.
+//!
+//! private urls can be downloaded with credentials. they are sent only to the host of the url,
+//! and not to the hosts redirected to. the token and netrc are not sent over plain `http://`,
+//! except to loopback hosts such as `localhost`.
+//! - `-H, --header <NAME: VALUE>`: HTTP header. can be repeated.
+//! - `RRCM_TOKEN` environment variable, or the one given by `--token-env <NAME>`: sent as `Authorization: Bearer <token>`
+//! - netrc (`NETRC`, or `~/.netrc`): login and password for the host, sent as basic authentication
+//! ```sh
+//! rrcm init <url> --sha256 <hex>
+//! rrcm init <url> --minisign-key RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3
+//! RRCM_TOKEN=<token> rrcm init <url>
+//! rrcm init <url> --header 'Authorization: token <token>'
+//! ```
+//!
 //! update(git clone or pull) repositories and deploy
 //! ```sh
 //! rrcm update
@@ -132,11 +387,79 @@
 //! rrcm status
 //! ```
 //!
+//! add repository to configuration file. comments in the file are preserved.
+//! deploy targets are given as `<target>[:<os>]=<path>`. os is `default` if omitted.
+//! with `--update`, the repository is cloned and deployed immediately.
+//! ```sh
+//! rrcm repo add example1 'git@github:example/example1' --target 'home=${HOME}' --target '.ssh:unix=${HOME}/.ssh' --update
+//! ```
+//!
+//! remove repository from configuration file. with `--undeploy`, the repository is undeployed before removing.
+//! ```sh
+//! rrcm repo remove example1 --undeploy
+//! ```
+//!
+//! list repositories.
+//! ```sh
+//! rrcm repo list
+//! ```
+//!
+//! validate configuration file.
+//! Problems are reported with file, line and column, and exits with non-zero status if any error is found.
+//! ```sh
+//! rrcm config validate
+//! ```
+//!
+//! convert configuration file to another format(yaml, toml or json).
+//! The converted file is written next to the original, and the original is moved to `config.yaml.bak`.
+//! Comments are not preserved.
+//! TOML has no null, so converting a file with `~`, e.g. of host rules, fails.
+//! `--drop-nulls` removes them, which may deploy targets excluded by host rules.
+//! A file downloaded by `rrcm init <url>` is not converted, since `rrcm config pull` reads the source
+//! in the format of the local file. Convert the source instead, or remove `config.source.yaml` to keep the file local.
+//! ```sh
+//! rrcm config convert --to toml
+//! ```
+//!
+//! migrate configuration file to the current version.
+//! The original file is kept as `config.yaml.bak`.
+//! `version` is updated in place, keeping comments and formatting of YAML and TOML.
+//! JSON files, and migrations changing the structure, rewrite the whole file without comments, which is shown in the summary.
+//! ```sh
+//! rrcm config migrate
+//! ```
+//!
+//! print configuration files in order of loading.
+//! ```sh
+//! rrcm config path
+//! ```
+//!
+//! print configuration file. with `--resolved`, the configuration merged with other configuration files,
+//! the local override, included files and environment variables is printed in YAML.
+//! ```sh
+//! rrcm config show --resolved
+//! ```
+//!
+//! pull configuration file again from the url or path recorded by `rrcm init`.
+//! changed repositories and deploy targets are shown (`+` added, `-` removed, `~` changed),
+//! and applied after confirmation, or immediately with `--yes`. The original file is kept as `config.yaml.bak`.
+//! local changes since the last download are reported before they are overwritten.
+//! the file is verified again as recorded by `rrcm init`. credentials are given as for `rrcm init`.
+//! ```sh
+//! rrcm config pull
+//! ```
+//!
+//! print JSON Schema of configuration file.
+//! To enable completion and validation with [yaml-language-server](https://github.com/redhat-developer/yaml-language-server),
+//! save it and add `# yaml-language-server: $schema=<path to schema>` to the top of config.yaml.
+//! ```sh
+//! rrcm config schema > rrcm.schema.json
+//! ```
+//!
 use anyhow::{Ok, Result};
 use clap::{Parser, Subcommand};
 use simplelog::{ColorChoice, CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode};
 use std::path::PathBuf;
-use url::Url;
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -150,7 +473,7 @@ struct Args {
     #[command(flatten)]
     log: LogArgs,
 
-    /// config file path
+    /// config file path. config files are discovered if not given. [env: RRCM_CONFIG]
     #[clap(required = false, short, long)]
     config: Option<PathBuf>,
 
@@ -178,23 +501,57 @@ struct LogArgs {
     quiet: bool,
 }
 
+/// Options of HTTP requests.
+#[derive(Debug, clap::Args)]
+struct HttpArgs {
+    /// HTTP header sent to the host of the url. e.g. "Authorization: Bearer <token>"
+    #[clap(short = 'H', long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<String>,
+    /// environment variable of the token sent as bearer token. [default: RRCM_TOKEN]
+    #[clap(long, value_name = "NAME")]
+    token_env: Option<String>,
+}
+
+impl From<&HttpArgs> for rrcm::config::HttpOptions {
+    fn from(args: &HttpArgs) -> Self {
+        Self {
+            headers: args.headers.clone(),
+            token_env: args.token_env.clone(),
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 enum SubCommands {
     /// Initialize configuration file.
     Init {
-        /// rrcm file url
-        #[clap(required = false, ignore_case = true)]
-        url: Option<Url>,
+        /// rrcm file url, local path or `-` for stdin
+        #[clap(required = false, value_name = "URL|PATH|-")]
+        source: Option<rrcm::config::Location>,
+        /// expected SHA-256 digest of the downloaded file in hex.
+        #[clap(long, value_name = "HEX", requires = "source")]
+        sha256: Option<String>,
+        /// verify minisign signature `<url>.minisig` with the public key. base64 or file path.
+        #[clap(long, value_name = "KEY", requires = "source")]
+        minisign_key: Option<String>,
+        /// verify SSH signature `<url>.sig` with the allowed signers file.
+        #[clap(long, value_name = "FILE", requires_all = ["source", "ssh_identity"])]
+        ssh_allowed_signers: Option<PathBuf>,
+        /// identity of the SSH signer in the allowed signers file.
+        #[clap(long, value_name = "IDENTITY", requires = "ssh_allowed_signers")]
+        ssh_identity: Option<String>,
+        #[clap(flatten)]
+        http: HttpArgs,
     },
     /// Print deploy status.
     Status {
-        /// repository name
+        /// repository name [env: RRCM_REPOS]
         #[clap(required = false, ignore_case = true)]
         repo: Option<String>,
     },
     /// Deploy file or folder.
     Deploy {
-        /// repository name
+        /// repository name [env: RRCM_REPOS]
         #[clap(required = false, ignore_case = true)]
         repo: Option<String>,
         /// if eists file, remove and deploy.  
@@ -203,19 +560,91 @@ enum SubCommands {
     },
     /// Deploy file or folder.
     Undeploy {
-        /// repository name
+        /// repository name [env: RRCM_REPOS]
         #[clap(required = false, ignore_case = true)]
         repo: Option<String>,
     },
     /// Update repository.
     Update {
-        /// repository name
+        /// repository name [env: RRCM_REPOS]
         #[clap(required = false, ignore_case = true)]
         repo: Option<String>,
         /// if eists file, remove and deploy.  
         #[clap(short, long, default_value_t = false)]
         force: bool,
     },
+    /// Manage repositories in configuration file.
+    Repo {
+        #[clap(subcommand)]
+        subcommand: RepoCommands,
+    },
+    /// Manage configuration file.
+    Config {
+        #[clap(subcommand)]
+        subcommand: ConfigCommands,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum RepoCommands {
+    /// Add repository to configuration file.
+    Add {
+        /// repository name
+        name: String,
+        /// git repository url
+        url: String,
+        /// deploy target, <target>[:<os>]=<path>. os is default if omitted.
+        #[clap(long = "target", value_name = "TARGET")]
+        targets: Vec<String>,
+        /// clone and deploy the repository.
+        #[clap(short, long, default_value_t = false)]
+        update: bool,
+    },
+    /// Remove repository from configuration file.
+    Remove {
+        /// repository name
+        name: String,
+        /// undeploy the repository before removing.
+        #[clap(short, long, default_value_t = false)]
+        undeploy: bool,
+    },
+    /// List repositories.
+    List,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommands {
+    /// Print JSON Schema of configuration file.
+    Schema,
+    /// Validate configuration file and included files.
+    Validate,
+    /// Migrate configuration file to the current version.
+    Migrate,
+    /// Convert configuration file to another format.
+    Convert {
+        /// format to convert to: yaml, toml or json
+        #[clap(long)]
+        to: rrcm::config::Format,
+        /// remove null values, which the format can not represent
+        #[clap(long)]
+        drop_nulls: bool,
+    },
+    /// Print configuration files in order of loading.
+    Path,
+    /// Print configuration file.
+    Show {
+        /// print the configuration merged with other configuration files, the local override and included files.
+        #[clap(long, default_value_t = false)]
+        resolved: bool,
+    },
+    /// Pull configuration file again from where it was initialized, showing the changes.
+    Pull {
+        /// apply the changes without confirmation.
+        #[clap(short, long, default_value_t = false)]
+        yes: bool,
+        #[clap(flatten)]
+        http: HttpArgs,
+    },
 }
 
 fn main() {
@@ -223,38 +652,50 @@ fn main() {
         let args = Args::parse();
         init_logger(&args.log)?;
 
-        let config = if let Some(ref path) = args.config {
-            path.clone()
-        } else {
-            dirs::config_dir()
-                .ok_or_else(|| anyhow::anyhow!("config directory not found"))?
-                .join("rrcm")
-                .join("config.yaml")
-        };
+        let files = rrcm::config::discover_app_config(args.config.as_deref())?;
+        let config = files.primary().to_path_buf();
 
         match args.subcommand {
-            SubCommands::Init { url } => {
-                // download config file if url is specified
-                if let Some(url) = url {
-                    rrcm::config::download_app_config(&config, url)?;
+            SubCommands::Init {
+                source,
+                sha256,
+                minisign_key,
+                ssh_allowed_signers,
+                ssh_identity,
+                http,
+            } => {
+                // read config file if source is specified
+                if let Some(source) = source {
+                    let verification = rrcm::config::Verification {
+                        sha256,
+                        minisign_key,
+                        ssh_allowed_signers,
+                        ssh_identity,
+                    };
+                    rrcm::config::download_app_config(
+                        &config,
+                        &source,
+                        &verification,
+                        &(&http).into(),
+                    )?;
                 } else {
                     rrcm::config::init_app_config(&config)?;
                 }
             }
             SubCommands::Status { ref repo } => {
-                let app_config = rrcm::config::load_app_config(&config)?;
+                let app_config = files.load()?;
                 rrcm::status(&app_config, repo)?;
             }
             SubCommands::Deploy { ref repo, force } => {
-                let app_config = rrcm::config::load_app_config(&config)?;
+                let app_config = files.load()?;
                 rrcm::deploy(&app_config, repo, args.log.quiet, force)?;
             }
             SubCommands::Undeploy { ref repo } => {
-                let app_config = rrcm::config::load_app_config(&config)?;
+                let app_config = files.load()?;
                 rrcm::undeploy(&app_config, repo, args.log.quiet)?;
             }
             SubCommands::Update { ref repo, force } => {
-                let app_config = rrcm::config::load_app_config(&config)?;
+                let app_config = files.load()?;
                 rrcm::update(
                     &app_config,
                     repo,
@@ -263,6 +704,59 @@ fn main() {
                     force,
                 )?;
             }
+            SubCommands::Repo { ref subcommand } => match subcommand {
+                RepoCommands::Add {
+                    name,
+                    url,
+                    targets,
+                    update,
+                } => {
+                    rrcm::repo_add(&config, name, url, targets)?;
+                    if *update {
+                        let app_config = files.load()?;
+                        rrcm::update(
+                            &app_config,
+                            &Some(name.clone()),
+                            args.log.quiet,
+                            args.log.verbose || args.log.debug || args.log.trace,
+                            false,
+                        )?;
+                    }
+                }
+                RepoCommands::Remove { name, undeploy } => {
+                    if *undeploy {
+                        let app_config = files.load()?;
+                        rrcm::undeploy(&app_config, &Some(name.clone()), args.log.quiet)?;
+                    }
+                    rrcm::repo_remove(&config, name)?;
+                }
+                RepoCommands::List => {
+                    rrcm::repo_list(&files)?;
+                }
+            },
+            SubCommands::Config { ref subcommand } => match subcommand {
+                ConfigCommands::Schema => {
+                    println!("{}", rrcm::config::app_config_schema()?);
+                }
+                ConfigCommands::Validate => {
+                    rrcm::validate(&config)?;
+                }
+                ConfigCommands::Migrate => {
+                    rrcm::migrate(&config)?;
+                }
+                ConfigCommands::Convert { to, drop_nulls } => {
+                    rrcm::convert(&config, *to, *drop_nulls)?;
+                }
+                ConfigCommands::Path => {
+                    rrcm::config_path(&files)?;
+                }
+                ConfigCommands::Show { resolved } => {
+                    rrcm::show(&files, *resolved)?;
+                }
+                ConfigCommands::Pull { yes, http } => {
+                    rrcm::pull(&config, &http.into(), *yes)?;
+                }
+            },
         }
         Ok(())
     })()
@@ -44,6 +44,17 @@ fn is_xdg_base_directory(s: &str) -> bool {
 /// If VARNAME is XDG Base Directory, it is expanded to the path of the XDG Base Directory
 /// https://specifications.freedesktop.org/basedir-spec/basedir-spec-latest.html
 pub fn expand_env_var(s: &str) -> Result<String> {
+    expand_var(s, |_| Ok(None))
+}
+
+/// Expand variable in String
+/// ${VARNAME} format is expanded
+/// VARNAME is looked up by `lookup` first,
+/// and falls back to XDG Base Directory and environment variable.
+pub fn expand_var<F>(s: &str, mut lookup: F) -> Result<String>
+where
+    F: FnMut(&str) -> Result<Option<String>>,
+{
     let mut result = String::new();
     let mut chars = s.chars();
 
@@ -66,6 +77,10 @@ pub fn expand_env_var(s: &str) -> Result<String> {
                     if varname.is_empty() {
                         bail!("variable name is empty");
                     }
+                    if let Some(value) = lookup(&varname)? {
+                        result.push_str(&value);
+                        continue;
+                    }
                     if is_xdg_base_directory(&varname) {
                         result.push_str(&get_xdg_default(&varname)?);
                         continue;
@@ -120,6 +135,22 @@ mod tests {
         assert_eq!(result.unwrap_err().to_string(), expected);
     }
 
+    #[rstest]
+    #[case("${WORK_ROOT}/foo", "/work/foo")]
+    #[case("${XDG_CONFIG_HOME}", "/config")]
+    #[case("${HOME}", std::env::var("HOME").unwrap())]
+    fn test_expand_var(#[case] s: &str, #[case] expected: String) -> Result<()> {
+        let result = expand_var(s, |name| {
+            Ok(match name {
+                "WORK_ROOT" => Some("/work".to_string()),
+                "XDG_CONFIG_HOME" => Some("/config".to_string()),
+                _ => None,
+            })
+        })?;
+        assert_eq!(result, expected);
+        Ok(())
+    }
+
     #[rstest]
     #[case("XDG_CONFIG_HOME", true)]
     #[case("XDG_DATA_HOME", true)]
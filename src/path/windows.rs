@@ -59,6 +59,17 @@ fn is_known_folder_id(s: &str) -> bool {
 /// If VARNAME is KNOWNFOLDERID, expand to path of known folder
 /// https://docs.microsoft.com/ja-jp/windows/win32/shell/knownfolderid
 pub fn expand_env_var(s: &str) -> Result<String> {
+    expand_var(s, |_| Ok(None))
+}
+
+/// Expand variable on windows
+/// %VARNAME% format is supported
+/// VARNAME is looked up by `lookup` first,
+/// and falls back to known folder and environment variable.
+pub fn expand_var<F>(s: &str, mut lookup: F) -> Result<String>
+where
+    F: FnMut(&str) -> Result<Option<String>>,
+{
     let mut result = String::new();
     let mut chars = s.chars();
 
@@ -80,6 +91,10 @@ pub fn expand_env_var(s: &str) -> Result<String> {
             if varname.is_empty() {
                 bail!("invalid environment variable: {}", s);
             }
+            if let Some(value) = lookup(&varname)? {
+                result.push_str(&value);
+                continue;
+            }
             if is_known_folder_id(&varname) {
                 result.push_str(&get_known_folder(&varname)?);
                 continue;
@@ -152,6 +167,21 @@ mod tests {
         assert_eq!(expand_env_var(input).unwrap(), expected);
     }
 
+    #[rstest]
+    #[case("%WORK_ROOT%\\foo", "C:\\work\\foo".to_string())]
+    #[case("%FOLDERID_Desktop%", "C:\\desktop".to_string())]
+    #[case("%USERPROFILE%", dirs::home_dir().unwrap().to_str().unwrap().to_string())]
+    fn test_expand_var(#[case] input: &str, #[case] expected: String) {
+        let result = expand_var(input, |name| {
+            Ok(match name {
+                "WORK_ROOT" => Some("C:\\work".to_string()),
+                "FOLDERID_Desktop" => Some("C:\\desktop".to_string()),
+                _ => None,
+            })
+        });
+        assert_eq!(result.unwrap(), expected);
+    }
+
     #[rstest]
     #[case("%USERPROFILE")]
     #[case("%USERPROFILE2%")]
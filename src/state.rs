@@ -0,0 +1,178 @@
+//! Records of deployed copies and created directories.
+//!
+//! Targets deployed by `mode: copy` are recorded with the digest of the content when copied,
+//! so that changes of the copy can be told from updates of the repository.
+//! Directories created by `recursive: true` are recorded to be removed or folded back on undeploy.
+//! The records are kept in `.rrcm-state.yaml` in the dotfiles directory.
+use crate::fs;
+use anyhow::{Context as _, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+const STATE_FILE_NAME: &str = ".rrcm-state.yaml";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyRecord {
+    /// Deploy source in the repository.
+    pub from: PathBuf,
+    /// SHA-256 digest of the content when copied.
+    pub sha256: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct State {
+    /// Copies keyed by the destination.
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    copies: IndexMap<PathBuf, CopyRecord>,
+    /// Directories created at the destination.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    directories: Vec<PathBuf>,
+    /// Directories of the repositories, whose entries are deployed by rrcm.
+    #[serde(skip)]
+    repos: Vec<PathBuf>,
+}
+
+impl State {
+    pub fn path(dotfiles: &Path) -> PathBuf {
+        dotfiles.join(STATE_FILE_NAME)
+    }
+
+    /// Load the state in the dotfiles directory. Empty if not exists.
+    pub fn load(dotfiles: &Path) -> Result<Self> {
+        let path = Self::path(dotfiles);
+        let state = if path.exists() {
+            let text = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            serde_yml::from_str(&text)
+                .with_context(|| format!("Failed to load {}", path.display()))?
+        } else {
+            Self::default()
+        };
+        Ok(state)
+    }
+
+    /// Set the directories of the repositories, which may be outside the dotfiles directory by `dir`.
+    pub fn with_repos(mut self, repos: Vec<PathBuf>) -> Self {
+        self.repos = repos;
+        self
+    }
+
+    pub fn save(&self, dotfiles: &Path) -> Result<()> {
+        let path = Self::path(dotfiles);
+        if self.copies.is_empty() && self.directories.is_empty() && !path.exists() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(dotfiles)?;
+        std::fs::write(&path, serde_yml::to_string(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Whether the path is in the directory of a repository, i.e. deployed by rrcm.
+    pub fn is_managed(&self, path: &Path) -> bool {
+        let Ok(path) = dunce::canonicalize(path) else {
+            return false;
+        };
+        self.repos
+            .iter()
+            .filter_map(|repo| dunce::canonicalize(repo).ok())
+            .any(|repo| path.starts_with(repo))
+    }
+
+    /// Record of the copy deployed from `from` to `to`.
+    pub fn copy(&self, from: &Path, to: &Path) -> Option<&CopyRecord> {
+        self.copies.get(to).filter(|record| record.from == from)
+    }
+
+    /// Record the copy with the digest of the current content.
+    pub fn record_copy(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let record = CopyRecord {
+            from: from.to_path_buf(),
+            sha256: fs::content_hash(to)?,
+        };
+        self.copies.insert(to.to_path_buf(), record);
+        Ok(())
+    }
+
+    pub fn remove_copy(&mut self, to: &Path) {
+        self.copies.shift_remove(to);
+    }
+
+    /// Created directories, deepest first.
+    pub fn directories(&self) -> Vec<PathBuf> {
+        let mut directories = self.directories.clone();
+        directories.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        directories
+    }
+
+    pub fn record_directory(&mut self, dir: &Path) {
+        if !self.directories.iter().any(|d| d == dir) {
+            self.directories.push(dir.to_path_buf());
+        }
+    }
+
+    pub fn remove_directory(&mut self, dir: &Path) {
+        self.directories.retain(|d| d != dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_state() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let from = temp.child("repo/home/.profile");
+        from.write_str("export A=1\n")?;
+        let to = temp.child("home/.profile");
+        to.write_str("export A=1\n")?;
+
+        let dotfiles = temp.path().join("dotfiles");
+        let mut state = State::load(&dotfiles)?;
+        assert_eq!(state, State::default());
+        state.save(&dotfiles)?;
+        assert!(!State::path(&dotfiles).exists());
+
+        state.record_copy(from.path(), to.path())?;
+        state.save(&dotfiles)?;
+        let state = State::load(&dotfiles)?;
+        let record = state.copy(from.path(), to.path()).unwrap();
+        assert_eq!(record.sha256, fs::content_hash(from.path())?);
+        assert_eq!(state.copy(temp.path(), to.path()), None);
+        assert!(!state.is_managed(from.path()));
+
+        let mut state = state.with_repos(vec![temp.path().join("repo")]);
+        assert!(state.is_managed(from.path()));
+        assert!(!state.is_managed(to.path()));
+
+        state.remove_copy(to.path());
+        assert_eq!(state.copy(from.path(), to.path()), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_directories() -> Result<()> {
+        let temp = assert_fs::TempDir::new()?;
+        let dotfiles = temp.path().join("dotfiles");
+        let config = temp.path().join("home/.config");
+
+        let mut state = State::default();
+        state.record_directory(&config);
+        state.record_directory(&config.join("nvim/lua"));
+        state.record_directory(&config.join("nvim"));
+        state.record_directory(&config);
+        state.save(&dotfiles)?;
+
+        let mut state = State::load(&dotfiles)?;
+        assert_eq!(
+            state.directories(),
+            vec![config.join("nvim/lua"), config.join("nvim"), config.clone()]
+        );
+        state.remove_directory(&config.join("nvim"));
+        assert_eq!(state.directories(), vec![config.join("nvim/lua"), config]);
+        Ok(())
+    }
+}
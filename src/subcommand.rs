@@ -3,26 +3,77 @@
 //! This module contains subcommands.
 //! Each subcommand is implemented as a function.
 use crate::config::AppConfig;
-use crate::config::Repository;
-use crate::deploy_status::{get_status, DeployStatus};
+use crate::config::{
+    add_repository, apply_pulled_app_config, backup_path, convert_app_config, is_locally_modified,
+    local_path, migrate_app_config, pull_app_config, remove_repository, validate_app_config,
+    Change, ConfigFiles, Format, HttpOptions, Severity, CURRENT_VERSION,
+};
+use crate::config::{DeployMode, Repository, Source};
+use crate::deploy_status::{get_copy_status, get_hardlink_status, get_status, DeployStatus};
 use crate::fs;
+use crate::git;
+use crate::host;
+use crate::state::State;
+use crate::when;
 use anyhow::{bail, Context as _, Ok, Result};
+use indexmap::IndexMap;
 use itertools::Itertools;
-use nu_ansi_term::Color::{Fixed, Green, Red, Yellow};
-use std::fs::{read_dir, ReadDir};
+use nu_ansi_term::Color::{Cyan, Fixed, Green, Purple, Red, Yellow};
+use std::fs::{read_dir, DirEntry, ReadDir};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+
+/// Options of a deploy target.
+#[derive(Debug, Clone, Copy)]
+struct DeployOptions {
+    mode: DeployMode,
+    relative_links: Option<bool>,
+    recursive: bool,
+}
+
+/// Deploy source directory of a target and its destination.
+#[derive(Debug)]
+struct DeployPath {
+    from_path: PathBuf,
+    from_readdir: ReadDir,
+    to_path: PathBuf,
+    options: DeployOptions,
+}
+
+/// Deploy status of an entry of deploy source directory.
+#[derive(Debug)]
+struct DeployEntry {
+    status: DeployStatus,
+    from: PathBuf,
+    to: PathBuf,
+    options: DeployOptions,
+}
 
 fn create_deploy_path<'a, P>(
     path: P,
     repo: &'a Repository,
-) -> impl Iterator<Item = Result<(PathBuf, ReadDir, PathBuf)>> + 'a
+    vars: &'a IndexMap<String, String>,
+) -> impl Iterator<Item = Result<DeployPath>> + 'a
 where
     P: AsRef<Path> + 'a,
 {
-    let path = path.as_ref().to_path_buf();
-    let repo_path = path.join(&repo.name);
-    repo.deploy.iter().map(move |(from_dirname, to)| {
+    let repo_path = path.as_ref().to_path_buf();
+    let context = when::Context::new(vars);
+    // skip targets by their conditions
+    let deploy = repo
+        .resolve_deploy(&host::hostname())
+        .into_iter()
+        .filter_map(|(name, to)| match to.skip_reason(&context) {
+            Result::Ok(None) => Some(Ok((name, to))),
+            Result::Ok(Some(reason)) => {
+                log::info!("Skip {:}: {:}", name, reason);
+                None
+            }
+            Err(e) => Some(Err(e)),
+        })
+        .collect_vec();
+    deploy.into_iter().map(move |target| {
+        let (from_dirname, to) = target?;
         let from_path = repo_path.join(from_dirname);
         let from_readdir = read_dir(&from_path).with_context(|| {
             format!(
@@ -30,95 +81,175 @@ where
                 from_path.to_string_lossy()
             )
         })?;
-        let to_path = to.to_pathbuf().with_context(|| {
+        let to_path = to.path.to_pathbuf_with(vars).with_context(|| {
             format!(
                 "Failed to read deploy destination directory \"{:}\"",
                 from_dirname
             )
         })?;
-        Ok((from_path, from_readdir, to_path))
+        Ok(DeployPath {
+            from_path,
+            from_readdir,
+            to_path,
+            options: DeployOptions {
+                mode: to.mode,
+                relative_links: to.relative_links,
+                recursive: to.recursive,
+            },
+        })
     })
 }
 
-fn create_deploy_status(
-    deploy_status_list: Vec<(PathBuf, ReadDir, PathBuf)>,
-) -> impl Iterator<Item = Result<(DeployStatus, PathBuf, PathBuf)>> {
-    deploy_status_list
-        .into_iter()
-        .flat_map(|(from_path, from_readdir, to_path)| {
-            from_readdir.map(move |entry| {
-                let from = entry
-                    .with_context(|| {
-                        format!(
-                            "Failed to read deploy source directory entry {:}",
-                            from_path.to_string_lossy()
-                        )
-                    })?
-                    .path();
-
-                let to = to_path.join(from.file_name().with_context(|| {
-                    format!("Failed to get file name from {:}", from.to_string_lossy())
-                })?);
-
-                Ok((get_status(&from, &to), from, to))
-            })
-        })
+/// Print the item skipped by its condition.
+fn print_skipped(item: &str, reason: &str) {
+    println!(
+        "{:>12} {:} ({:})",
+        Fixed(8).paint(format!("{:>12}", "Skipped")),
+        item,
+        reason
+    );
 }
 
-fn deploy_impl<P>(repo: &Repository, path: P, quiet: bool, force: bool) -> Result<()>
-where
-    P: AsRef<Path>,
-{
-    log::trace!("deploy_impl({:?}, {:?}, {:?})", path.as_ref(), quiet, force);
+/// Paths of the entry of `from_dir` and its destination in `to_dir`.
+fn entry_paths(
+    entry: std::io::Result<DirEntry>,
+    from_dir: &Path,
+    to_dir: &Path,
+) -> Result<(PathBuf, PathBuf)> {
+    let from = entry
+        .with_context(|| {
+            format!(
+                "Failed to read deploy source directory entry {:}",
+                from_dir.to_string_lossy()
+            )
+        })?
+        .path();
 
-    let path = path.as_ref();
-    let deploy_paths = create_deploy_path(path, repo)
-        .inspect(|r| {
-            log::debug!("Deploy path: {:?}", r);
-        })
-        .filter_map(Result::ok)
-        .collect();
+    let to = to_dir
+        .join(from.file_name().with_context(|| {
+            format!("Failed to get file name from {:}", from.to_string_lossy())
+        })?);
+    Ok((from, to))
+}
 
-    create_deploy_status(deploy_paths)
-        .inspect(|r| {
-            log::debug!("Deploy status: {:?}", r);
+fn entry_status(from: &Path, to: &Path, options: DeployOptions, state: &State) -> DeployStatus {
+    match options.mode {
+        DeployMode::Symlink => match get_status(from, to) {
+            // a link to another directory deployed by rrcm is unfolded into a directory of links
+            DeployStatus::Conflict { .. }
+                if options.recursive
+                    && from.is_dir()
+                    && to.is_symlink()
+                    && to.is_dir()
+                    && fs::link_target(to).is_ok_and(|target| state.is_managed(&target)) =>
+            {
+                DeployStatus::UnDeployed
+            }
+            status => status,
+        },
+        DeployMode::Copy => get_copy_status(from, to, state.copy(from, to)),
+        DeployMode::Hardlink => get_hardlink_status(from, to),
+    }
+}
+
+/// Deploy status of the entry. With `recursive`, a directory existing at the destination
+/// is descended into, and the status of each entry in it is returned.
+fn expand_entry(
+    from: PathBuf,
+    to: PathBuf,
+    options: DeployOptions,
+    state: &State,
+) -> Vec<Result<DeployEntry>> {
+    if !(options.recursive && from.is_dir() && fs::is_real_dir(&to)) {
+        let status = entry_status(&from, &to, options, state);
+        return vec![Ok(DeployEntry {
+            status,
+            from,
+            to,
+            options,
+        })];
+    }
+    let readdir = match read_dir(&from) {
+        Result::Ok(readdir) => readdir,
+        Err(e) => {
+            return vec![Err(e).with_context(|| {
+                format!(
+                    "Failed to read deploy source directory {:}",
+                    from.to_string_lossy()
+                )
+            })]
+        }
+    };
+    readdir
+        .flat_map(|entry| match entry_paths(entry, &from, &to) {
+            Result::Ok((from, to)) => expand_entry(from, to, options, state),
+            Err(e) => vec![Err(e)],
         })
-        .collect::<Result<Vec<_>>>()?
-        .into_iter()
-        .map(|(status, from, to)| match status {
-            DeployStatus::UnDeployed => {
-                fs::symlink(&from, &to).with_context(|| {
-                    format!(
-                        "Failed to create symlink {:} -> {:}",
-                        from.to_string_lossy(),
-                        to.to_string_lossy()
-                    )
-                })?;
+        .collect()
+}
 
-                if !quiet {
-                    print_deploy_status(&DeployStatus::Deployed, &from, &to)?;
+fn create_deploy_status(
+    deploy_paths: Vec<DeployPath>,
+    state: &State,
+) -> impl Iterator<Item = Result<DeployEntry>> + '_ {
+    deploy_paths.into_iter().flat_map(move |deploy_path| {
+        let DeployPath {
+            from_path,
+            from_readdir,
+            to_path,
+            options,
+        } = deploy_path;
+        from_readdir.flat_map(
+            move |entry| match entry_paths(entry, &from_path, &to_path) {
+                Result::Ok((from, to)) => expand_entry(from, to, options, state),
+                Err(e) => vec![Err(e)],
+            },
+        )
+    })
+}
+
+/// Deploys entries, recording copies and created directories in `state`.
+struct Deployer<'a> {
+    state: &'a mut State,
+    relative_links: bool,
+    quiet: bool,
+    force: bool,
+}
+
+impl Deployer<'_> {
+    fn deploy(&mut self, entry: DeployEntry) -> Result<()> {
+        let DeployEntry {
+            status,
+            from,
+            to,
+            options,
+        } = entry;
+        match status {
+            DeployStatus::UnDeployed => self.deploy_entry(&from, &to, options),
+            DeployStatus::Deployed => Ok(()),
+            // the repository is updated, and the copy is not changed since deployed
+            DeployStatus::Outdated => {
+                fs::remove_all(&to)
+                    .with_context(|| format!("Failed to remove file {:}", to.to_string_lossy()))?;
+                self.deploy_entry(&from, &to, options)
+            }
+            DeployStatus::Modified => {
+                if !self.force {
+                    bail!(
+                        "Copy is modified {:}. Use --force to overwrite.",
+                        to.to_string_lossy()
+                    );
                 }
-                Ok((from, to))
+                fs::remove(&to)
+                    .with_context(|| format!("Failed to remove file {:}", to.to_string_lossy()))?;
+                self.deploy_entry(&from, &to, options)
             }
-            DeployStatus::Deployed => Ok((from, to)),
             DeployStatus::Conflict { cause } => {
-                if force {
+                if self.force {
                     fs::remove(&to).with_context(|| {
                         format!("Failed to remove file {:}", to.to_string_lossy())
                     })?;
-
-                    fs::symlink(&from, &to).with_context(|| {
-                        format!(
-                            "Failed to create symlink {:} -> {:}",
-                            from.to_string_lossy(),
-                            to.to_string_lossy()
-                        )
-                    })?;
-
-                    if !quiet {
-                        print_deploy_status(&DeployStatus::Deployed, &from, &to)?;
-                    }
-                    return Ok((from, to));
+                    return self.deploy_entry(&from, &to, options);
                 }
 
                 Err(anyhow::anyhow!("{:}", cause).context(format!(
@@ -130,7 +261,155 @@ where
             DeployStatus::UnManaged => {
                 bail!("File not exists {:}", to.to_string_lossy());
             }
+        }
+    }
+
+    /// Deploy the entry by the mode.
+    fn deploy_entry(&mut self, from: &Path, to: &Path, options: DeployOptions) -> Result<()> {
+        if options.recursive && from.is_dir() {
+            match options.mode {
+                DeployMode::Symlink if to.is_symlink() => return self.unfold(from, to, options),
+                DeployMode::Hardlink => {
+                    self.create_dir(to)?;
+                    return self.deploy_children(from, to, options);
+                }
+                _ => {}
+            }
+        }
+
+        let relative_links = options.relative_links.unwrap_or(self.relative_links);
+        match options.mode {
+            DeployMode::Symlink if relative_links => {
+                fs::symlink_relative(from, to).with_context(|| {
+                    format!(
+                        "Failed to create relative symlink {:} -> {:}",
+                        from.to_string_lossy(),
+                        to.to_string_lossy()
+                    )
+                })?
+            }
+            DeployMode::Symlink => fs::symlink(from, to).with_context(|| {
+                format!(
+                    "Failed to create symlink {:} -> {:}",
+                    from.to_string_lossy(),
+                    to.to_string_lossy()
+                )
+            })?,
+            DeployMode::Copy => {
+                fs::copy(from, to).with_context(|| {
+                    format!(
+                        "Failed to copy {:} -> {:}",
+                        from.to_string_lossy(),
+                        to.to_string_lossy()
+                    )
+                })?;
+                self.state.record_copy(from, to)?
+            }
+            DeployMode::Hardlink => fs::hardlink(from, to).with_context(|| {
+                format!(
+                    "Failed to create hard link {:} -> {:}",
+                    from.to_string_lossy(),
+                    to.to_string_lossy()
+                )
+            })?,
+        }
+
+        if !self.quiet {
+            print_deploy_status(&DeployStatus::Deployed, from, to)?;
+        }
+        Ok(())
+    }
+
+    fn deploy_children(&mut self, from: &Path, to: &Path, options: DeployOptions) -> Result<()> {
+        let entries = expand_entry(from.to_path_buf(), to.to_path_buf(), options, self.state);
+        for entry in entries {
+            self.deploy(entry?)?;
+        }
+        Ok(())
+    }
+
+    /// Replace the link to another directory with a directory of links to its entries,
+    /// and deploy the entries of `from` into it.
+    fn unfold(&mut self, from: &Path, to: &Path, options: DeployOptions) -> Result<()> {
+        let target = fs::link_target(to)?;
+        let relative = std::fs::read_link(to)?.is_relative();
+        log::info!(
+            "Unfold {:} -> {:}",
+            to.to_string_lossy(),
+            target.to_string_lossy()
+        );
+
+        fs::remove_all(to)
+            .with_context(|| format!("Failed to remove symlink {:}", to.to_string_lossy()))?;
+        self.create_dir(to)?;
+        for entry in read_dir(&target)? {
+            let entry = entry?;
+            link(&entry.path(), &to.join(entry.file_name()), relative)?;
+        }
+        self.deploy_children(from, to, options)
+    }
+
+    fn create_dir(&mut self, dir: &Path) -> Result<()> {
+        std::fs::create_dir(dir)
+            .with_context(|| format!("Failed to create directory {:}", dir.to_string_lossy()))?;
+        self.state.record_directory(dir);
+        Ok(())
+    }
+}
+
+/// Create the symbolic link, relative to the directory of `to` if `relative`.
+fn link(from: &Path, to: &Path, relative: bool) -> Result<()> {
+    if relative {
+        fs::symlink_relative(from, to)
+    } else {
+        fs::symlink(from, to)
+    }
+    .with_context(|| {
+        format!(
+            "Failed to create symlink {:} -> {:}",
+            from.to_string_lossy(),
+            to.to_string_lossy()
+        )
+    })
+}
+
+fn deploy_impl<P>(
+    repo: &Repository,
+    path: P,
+    vars: &IndexMap<String, String>,
+    state: &mut State,
+    relative_links: bool,
+    quiet: bool,
+    force: bool,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    log::trace!("deploy_impl({:?}, {:?}, {:?})", path.as_ref(), quiet, force);
+
+    let path = path.as_ref();
+    let deploy_paths = create_deploy_path(path, repo, vars)
+        .inspect(|r| {
+            log::debug!("Deploy path: {:?}", r);
         })
+        .filter_map(Result::ok)
+        .collect();
+
+    let entries = create_deploy_status(deploy_paths, state)
+        .inspect(|r| {
+            log::debug!("Deploy status: {:?}", r);
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut deployer = Deployer {
+        state,
+        relative_links,
+        quiet,
+        force,
+    };
+    entries
+        .into_iter()
+        .map(|entry| deployer.deploy(entry))
         .inspect(|r| {
             log::debug!("Deploy result: {:?}", r);
         })
@@ -154,29 +433,40 @@ pub fn deploy(
     );
 
     app_config
-        .repos
-        .iter()
-        .filter(|repo| {
-            // if repo is specified, skip other repo.
-            if let Some(repo_name) = repo_name.as_ref() {
-                repo.name == *repo_name
-            } else {
-                true
-            }
-        })
+        .select_repos(repo_name)
+        .into_iter()
         .enumerate()
         .map(|(index, repo)| {
-            let path = app_config.to_pathbuf()?;
+            let vars = app_config.resolve_vars()?;
+            let dotfiles = app_config.dotfiles.to_pathbuf_with(&vars)?;
+            let path = repo.to_pathbuf_with(&dotfiles, &vars)?;
 
-            if !quiet {
-                if index > 0 {
-                    println!();
+            if !quiet && index > 0 {
+                println!();
+            }
+            if let Some(reason) = repo.skip_reason(&when::Context::new(&vars))? {
+                if !quiet {
+                    print_skipped(&repo.name, &reason);
                 }
+                return Ok(());
+            }
+            if !quiet {
                 println!("Deploy {:}", repo.name);
             }
 
             // deploy
-            deploy_impl(repo, path, quiet, force)?;
+            let mut state = load_state(app_config, &dotfiles, &vars)?;
+            let result = deploy_impl(
+                repo,
+                path,
+                &vars,
+                &mut state,
+                app_config.relative_links,
+                quiet,
+                force,
+            );
+            state.save(&dotfiles)?;
+            result?;
 
             Ok(())
         })
@@ -188,50 +478,157 @@ pub fn deploy(
     Ok(())
 }
 
-fn undeploy_impl<P>(repo: &Repository, path: P, quiet: bool) -> Result<()>
+/// Load the state of the dotfiles directory with the directories of all the repositories,
+/// since a link into any of them is deployed by rrcm.
+fn load_state(
+    app_config: &AppConfig,
+    dotfiles: &Path,
+    vars: &IndexMap<String, String>,
+) -> Result<State> {
+    let repos = app_config
+        .repos
+        .iter()
+        .filter_map(|repo| repo.to_pathbuf_with(dotfiles, vars).ok())
+        .collect();
+    Ok(State::load(dotfiles)?.with_repos(repos))
+}
+
+fn undeploy_impl<P>(
+    repo: &Repository,
+    path: P,
+    vars: &IndexMap<String, String>,
+    state: &mut State,
+    quiet: bool,
+) -> Result<()>
 where
     P: AsRef<Path>,
 {
     log::trace!("undeploy_impl({:?}, {:?})", path.as_ref(), quiet);
 
     let path = path.as_ref();
-    let deploy_paths = create_deploy_path(path, repo)
+    let deploy_paths: Vec<_> = create_deploy_path(path, repo, vars)
         .inspect(|r| {
             log::debug!("Deploy path: {:?}", r);
         })
         .filter_map(Result::ok)
         .collect();
+    let targets = deploy_paths
+        .iter()
+        .map(|deploy_path| deploy_path.to_path.clone())
+        .collect::<Vec<_>>();
 
-    create_deploy_status(deploy_paths)
+    create_deploy_status(deploy_paths, state)
         .inspect(|r| {
             log::debug!("Deploy status: {:?}", r);
         })
         .collect::<Result<Vec<_>>>()?
         .into_iter()
-        .map(|(status, from, to)| match status {
-            DeployStatus::UnDeployed => Ok((from, to)),
-            DeployStatus::Deployed => {
-                fs::remove(&to)
-                    .with_context(|| format!("Failed to remove file {:}", to.to_string_lossy()))?;
+        .map(
+            |DeployEntry {
+                 status, from, to, ..
+             }| match status {
+                DeployStatus::UnDeployed => {
+                    state.remove_copy(&to);
+                    Ok((from, to))
+                }
+                DeployStatus::Deployed | DeployStatus::Outdated => {
+                    fs::remove(&to).with_context(|| {
+                        format!("Failed to remove file {:}", to.to_string_lossy())
+                    })?;
+                    state.remove_copy(&to);
 
-                if !quiet {
-                    print_deploy_status(&DeployStatus::UnDeployed, &from, &to)?;
+                    if !quiet {
+                        print_deploy_status(&DeployStatus::UnDeployed, &from, &to)?;
+                    }
+                    Ok((from, to))
                 }
-                Ok((from, to))
-            }
-            DeployStatus::Conflict { .. } => Ok((from, to)),
-            DeployStatus::UnManaged => {
-                bail!("File not exists {:}", to.to_string_lossy());
-            }
-        })
+                // keep changes of the copy
+                DeployStatus::Modified => {
+                    log::warn!("Copy is modified. Keep {:}", to.to_string_lossy());
+                    Ok((from, to))
+                }
+                DeployStatus::Conflict { .. } => Ok((from, to)),
+                DeployStatus::UnManaged => {
+                    bail!("File not exists {:}", to.to_string_lossy());
+                }
+            },
+        )
         .inspect(|r| {
             log::debug!("Undeploy result: {:?}", r);
         })
         .collect::<Result<Vec<_>>>()?;
 
+    fold_directories(state, &targets)
+}
+
+/// Remove the directories created by recursive deployment under `targets` if empty,
+/// or fold them back into a link if all the entries link to the entries of a directory of a repository.
+fn fold_directories(state: &mut State, targets: &[PathBuf]) -> Result<()> {
+    let directories = state
+        .directories()
+        .into_iter()
+        .filter(|dir| targets.iter().any(|target| dir.starts_with(target)));
+    for dir in directories {
+        if !fs::is_real_dir(&dir) {
+            state.remove_directory(&dir);
+            continue;
+        }
+        let entries = read_dir(&dir)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        if entries.is_empty() {
+            std::fs::remove_dir(&dir).with_context(|| {
+                format!("Failed to remove directory {:}", dir.to_string_lossy())
+            })?;
+            state.remove_directory(&dir);
+        } else if let Some((target, relative)) =
+            fold_target(&entries)?.filter(|(target, _)| state.is_managed(target))
+        {
+            log::info!(
+                "Fold {:} -> {:}",
+                dir.to_string_lossy(),
+                target.to_string_lossy()
+            );
+            for entry in &entries {
+                fs::remove_all(entry)?;
+            }
+            std::fs::remove_dir(&dir)?;
+            link(&target, &dir, relative)?;
+            state.remove_directory(&dir);
+        }
+    }
     Ok(())
 }
 
+/// The directory whose entries are all linked by `entries` with the same names,
+/// and whether the links are relative.
+fn fold_target(entries: &[PathBuf]) -> Result<Option<(PathBuf, bool)>> {
+    let mut target: Option<PathBuf> = None;
+    for entry in entries {
+        if !entry.is_symlink() {
+            return Ok(None);
+        }
+        let linked = fs::link_target(entry)?;
+        let Some(parent) = linked.parent() else {
+            return Ok(None);
+        };
+        if linked.file_name() != entry.file_name()
+            || target.as_ref().is_some_and(|target| target != parent)
+        {
+            return Ok(None);
+        }
+        target = Some(parent.to_path_buf());
+    }
+    let Some(target) = target else {
+        return Ok(None);
+    };
+    if !target.is_dir() || read_dir(&target)?.count() != entries.len() {
+        return Ok(None);
+    }
+    let relative = std::fs::read_link(&entries[0])?.is_relative();
+    Ok(Some((target, relative)))
+}
+
 /// undeploy files
 /// # Arguments
 /// * `repo` - repo name
@@ -240,29 +637,32 @@ pub fn undeploy(app_config: &AppConfig, repo_name: &Option<String>, quiet: bool)
     log::trace!("undeploy({:?}, {:?}, {:?})", app_config, repo_name, quiet);
 
     app_config
-        .repos
-        .iter()
-        .filter(|repo| {
-            // if repo is specified, skip other repo.
-            if let Some(repo_name) = repo_name.as_ref() {
-                repo.name == *repo_name
-            } else {
-                true
-            }
-        })
+        .select_repos(repo_name)
+        .into_iter()
         .enumerate()
         .map(|(index, repo)| {
-            let path = app_config.to_pathbuf()?;
+            let vars = app_config.resolve_vars()?;
+            let dotfiles = app_config.dotfiles.to_pathbuf_with(&vars)?;
+            let path = repo.to_pathbuf_with(&dotfiles, &vars)?;
 
-            if !quiet {
-                if index > 0 {
-                    println!();
+            if !quiet && index > 0 {
+                println!();
+            }
+            if let Some(reason) = repo.skip_reason(&when::Context::new(&vars))? {
+                if !quiet {
+                    print_skipped(&repo.name, &reason);
                 }
+                return Ok(());
+            }
+            if !quiet {
                 println!("UnDeploy {:}", repo.name);
             }
 
             // undeploy
-            undeploy_impl(repo, path, quiet)?;
+            let mut state = load_state(app_config, &dotfiles, &vars)?;
+            let result = undeploy_impl(repo, path, &vars, &mut state, quiet);
+            state.save(&dotfiles)?;
+            result?;
 
             Ok(())
         })
@@ -293,6 +693,12 @@ where
             DeployStatus::UnManaged => Fixed(8)
                 .paint(format!("{:>12}", status.to_string()))
                 .to_string(),
+            DeployStatus::Modified => Purple
+                .paint(format!("{:>12}", status.to_string()))
+                .to_string(),
+            DeployStatus::Outdated => Cyan
+                .paint(format!("{:>12}", status.to_string()))
+                .to_string(),
         },
         {
             let from_str = from.as_ref().to_string_lossy();
@@ -303,7 +709,9 @@ where
                     format!("{:<20}", to_str)
                 }
                 DeployStatus::UnDeployed => format!("{:}", from_str),
-                DeployStatus::UnManaged => format!("{:}", to_str),
+                DeployStatus::UnManaged | DeployStatus::Modified | DeployStatus::Outdated => {
+                    format!("{:}", to_str)
+                }
                 DeployStatus::Conflict { cause } => {
                     format!("{:<20} {:}", to_str, cause,)
                 }
@@ -313,14 +721,26 @@ where
     Ok(())
 }
 
-fn status_impl<P>(repo: &Repository, path: P) -> Result<()>
+fn status_impl<P>(
+    repo: &Repository,
+    path: P,
+    vars: &IndexMap<String, String>,
+    state: &State,
+) -> Result<()>
 where
     P: AsRef<Path>,
 {
     log::trace!("status_impl({:?})", path.as_ref());
 
     let path = path.as_ref();
-    let deploy_paths = create_deploy_path(path, repo)
+    let context = when::Context::new(vars);
+    for (name, to) in repo.resolve_deploy(&host::hostname()) {
+        if let Result::Ok(Some(reason)) = to.skip_reason(&context) {
+            print_skipped(name, &reason);
+        }
+    }
+
+    let deploy_paths = create_deploy_path(path, repo, vars)
         .inspect(|result| {
             if let Err(e) = result {
                 log::warn!("{:?}", e);
@@ -331,7 +751,10 @@ where
 
     if log::log_enabled!(log::Level::Info) {
         log::info!("Deploy From => To ");
-        for (from_path, _, to_path) in &deploy_paths {
+        for DeployPath {
+            from_path, to_path, ..
+        } in &deploy_paths
+        {
             log::info!(
                 "{:} => {:}",
                 from_path.strip_prefix(path).unwrap().to_string_lossy(),
@@ -340,21 +763,29 @@ where
         }
     }
 
-    create_deploy_status(deploy_paths)
+    create_deploy_status(deploy_paths, state)
         .inspect(|result| {
             if let Err(e) = result {
                 log::warn!("{:?}", e);
             }
         })
         .filter_map(Result::ok)
-        .for_each(|(status, from, to)| {
-            if matches!(
-                status,
-                DeployStatus::Deployed | DeployStatus::UnDeployed | DeployStatus::Conflict { .. }
-            ) {
-                print_deploy_status(&status, from, to).expect("print error");
-            }
-        });
+        .for_each(
+            |DeployEntry {
+                 status, from, to, ..
+             }| {
+                if matches!(
+                    status,
+                    DeployStatus::Deployed
+                        | DeployStatus::UnDeployed
+                        | DeployStatus::Conflict { .. }
+                        | DeployStatus::Modified
+                        | DeployStatus::Outdated
+                ) {
+                    print_deploy_status(&status, from, to).expect("print error");
+                }
+            },
+        );
 
     Ok(())
 }
@@ -362,29 +793,41 @@ where
 pub fn status(app_config: &AppConfig, repo_name: &Option<String>) -> Result<()> {
     log::trace!("status({:?}, {:?})", app_config, repo_name);
     app_config
-        .repos
-        .iter()
-        .filter(|repo| {
-            // if repo is specified, skip other repo.
-            if let Some(repo_name) = repo_name.as_ref() {
-                repo.name == *repo_name
-            } else {
-                true
-            }
-        })
+        .select_repos(repo_name)
+        .into_iter()
         .enumerate()
         .map(|(index, repo)| {
-            let path = app_config.to_pathbuf()?;
+            let vars = app_config.resolve_vars()?;
+            let dotfiles = app_config.dotfiles.to_pathbuf_with(&vars)?;
+            let path = repo.to_pathbuf_with(&dotfiles, &vars)?;
 
             if index > 0 {
                 println!();
             }
             println!("Repo {:}", repo.name);
-            log::info!(
-                "{:} => {:}",
-                repo.url,
-                path.join(&repo.name).to_string_lossy()
-            );
+            if let Some(reason) = repo.skip_reason(&when::Context::new(&vars))? {
+                print_skipped(&repo.name, &reason);
+                return Ok(());
+            }
+            let source = repo.source()?;
+            log::info!("{:} => {:}", source, path.to_string_lossy());
+            if let Source::Path(_) = source {
+                println!("  Local directory {:}", path.to_string_lossy());
+            }
+            let hostname = host::hostname();
+            for pattern in repo.host_rules(&hostname) {
+                println!("  Host rule {:} matched {:}", pattern, hostname);
+            }
+            if let Some(pin) = repo.pin()?.filter(|_| path.exists()) {
+                println!("  Pinned to {:}", pin);
+                match git::divergence(&path, pin) {
+                    Result::Ok(Some(divergence)) => {
+                        println!("  {:} {:}", Yellow.paint("Diverged"), divergence)
+                    }
+                    Result::Ok(None) => {}
+                    Err(e) => log::warn!("{:?}", e),
+                }
+            }
 
             // TODO: repo自体のstatusを表示する
             // ディレクトリの存在チェック
@@ -393,7 +836,12 @@ pub fn status(app_config: &AppConfig, repo_name: &Option<String>) -> Result<()>
             // gitのbranchを表示する
             // gitのremoteを表示する
             // gitのtagを表示する
-            status_impl(repo, path)?;
+            status_impl(
+                repo,
+                path,
+                &vars,
+                &load_state(app_config, &dotfiles, &vars)?,
+            )?;
 
             Ok(())
         })
@@ -409,45 +857,22 @@ pub fn status(app_config: &AppConfig, repo_name: &Option<String>) -> Result<()>
 fn git_update(repo: &Repository, path: &Path, quiet: bool, verbose: bool) -> Result<()> {
     log::trace!("git_update({:?}, {:?})", repo, path);
 
-    let path = path.join(&repo.name);
+    let url = match repo.source()? {
+        Source::Url(url) => url,
+        // local directory is not managed by git
+        Source::Path(_) => {
+            log::info!("Skip git update of local directory {:?}", path);
+            return Ok(());
+        }
+    };
+    let pin = repo.pin()?;
 
     // update git repository
-    let mut git = Command::new("git");
     if path.exists() {
-        git.arg("pull").current_dir(path);
+        git::update(path, pin, quiet)
     } else {
-        git.arg("clone").arg(&repo.url).arg(path);
-        if verbose {
-            git.arg("-v");
-        }
-    }
-    if quiet {
-        git.arg("-q");
-    }
-
-    let output = git.output().with_context(|| {
-        format!(
-            "Failed to execute git {:}",
-            git.get_args().map(|s| s.to_string_lossy()).join(" ")
-        )
-    })?;
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let stderr = stderr.trim_end();
-    if !output.status.success() {
-        bail!("{}", stderr);
-    }
-
-    if !quiet && !stderr.is_empty() {
-        println!("{}", stderr);
+        git::clone(url, path, pin, quiet, verbose)
     }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stdout = stdout.trim_end();
-    if !stdout.is_empty() {
-        log::info!("{}", stdout);
-    }
-
-    Ok(())
 }
 
 /// Update dotfiles from git repository and deploy.
@@ -475,36 +900,43 @@ pub fn update(
     );
 
     app_config
-        .repos
-        .iter()
-        .filter(|repo| {
-            // if repo is specified, skip other repo.
-            if let Some(repo_name) = repo_name.as_ref() {
-                repo.name == *repo_name
-            } else {
-                true
-            }
-        })
+        .select_repos(repo_name)
+        .into_iter()
         .enumerate()
         .map(|(index, repo)| {
-            let path = app_config.to_pathbuf()?;
+            let vars = app_config.resolve_vars()?;
+            let dotfiles = app_config.dotfiles.to_pathbuf_with(&vars)?;
+            let path = repo.to_pathbuf_with(&dotfiles, &vars)?;
 
-            if !quiet {
-                if index > 0 {
-                    println!();
+            if !quiet && index > 0 {
+                println!();
+            }
+            if let Some(reason) = repo.skip_reason(&when::Context::new(&vars))? {
+                if !quiet {
+                    print_skipped(&repo.name, &reason);
                 }
+                return Ok(());
+            }
+            if !quiet {
                 println!("Update {:}", repo.name);
-                println!(
-                    "  {:} => {:}",
-                    repo.url,
-                    path.join(&repo.name).to_string_lossy()
-                );
+                println!("  {:} => {:}", repo.source()?, path.to_string_lossy());
             }
 
             git_update(repo, &path, quiet, verbose)?;
 
             // deploy
-            deploy_impl(repo, &path, quiet, force)?;
+            let mut state = load_state(app_config, &dotfiles, &vars)?;
+            let result = deploy_impl(
+                repo,
+                &path,
+                &vars,
+                &mut state,
+                app_config.relative_links,
+                quiet,
+                force,
+            );
+            state.save(&dotfiles)?;
+            result?;
 
             Ok(())
         })
@@ -515,3 +947,314 @@ pub fn update(
 
     Ok(())
 }
+
+/// Validate config file and print problems found.
+/// Returns error if any error is found.
+pub fn validate<P>(path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    log::trace!("validate({:?})", path.as_ref());
+
+    let path = path.as_ref();
+    let diagnostics = validate_app_config(path)?;
+    for diagnostic in &diagnostics {
+        println!(
+            "{}: {}: {}",
+            diagnostic.location(),
+            match diagnostic.severity {
+                Severity::Error => Red.paint(diagnostic.severity.to_string()),
+                Severity::Warning => Yellow.paint(diagnostic.severity.to_string()),
+            },
+            diagnostic.message
+        );
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .count();
+    if diagnostics.is_empty() {
+        println!("No problems found in {:}", path.to_string_lossy());
+    }
+    if errors > 0 {
+        bail!("{:} error(s) found in {:}", errors, path.to_string_lossy());
+    }
+    Ok(())
+}
+
+pub fn migrate<P>(path: P) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    log::trace!("migrate({:?})", path.as_ref());
+
+    let path = path.as_ref();
+    let migrated = migrate_app_config(path)?;
+    if migrated.changes.is_empty() {
+        println!(
+            "{:} is up to date (version {:})",
+            path.to_string_lossy(),
+            CURRENT_VERSION
+        );
+        return Ok(());
+    }
+
+    println!(
+        "Migrated {:} to version {:}",
+        path.to_string_lossy(),
+        CURRENT_VERSION
+    );
+    for change in &migrated.changes {
+        println!("  {}", change);
+    }
+    if migrated.rewritten {
+        println!("  The file is rewritten. Comments and formatting are not preserved.");
+    }
+    println!("Backup: {:}", backup_path(path).to_string_lossy());
+    Ok(())
+}
+
+pub fn convert<P>(path: P, to: Format, drop_nulls: bool) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    log::trace!("convert({:?}, {}, {})", path.as_ref(), to, drop_nulls);
+
+    let path = path.as_ref();
+    let converted = convert_app_config(path, to, drop_nulls)?;
+    for removed in &converted.removed {
+        log::warn!(
+            "{} is null and can not be represented in {}. Removed.",
+            removed,
+            to
+        );
+    }
+    println!(
+        "Converted {:} to {:}",
+        path.to_string_lossy(),
+        converted.path.to_string_lossy()
+    );
+    println!("Backup: {:}", converted.backup.to_string_lossy());
+    Ok(())
+}
+
+/// Ask the question on the terminal. Returns true if answered yes.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Pull config file from the source recorded by `rrcm init`,
+/// and apply the changes on confirmation.
+/// # Arguments
+/// * `http` - options of HTTP requests
+/// * `yes` - apply without confirmation
+pub fn pull<P>(path: P, http: &HttpOptions, yes: bool) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    log::trace!("pull({:?}, {:?}, {:?})", path.as_ref(), http, yes);
+
+    let path = path.as_ref();
+    let pulled = pull_app_config(path, http)?;
+    if !pulled.is_changed() {
+        println!(
+            "{:} is up to date with {:}",
+            path.to_string_lossy(),
+            pulled.source.url
+        );
+        return Ok(());
+    }
+
+    println!("{:} has changed", pulled.source.url);
+    if is_locally_modified(path, &pulled.source)? {
+        log::warn!(
+            "{} has local changes, which will be overwritten.",
+            path.display()
+        );
+    }
+    if pulled.differences.is_empty() {
+        println!("  No changes in repositories and deploy targets");
+    }
+    for difference in &pulled.differences {
+        let line = difference.to_string();
+        println!(
+            "  {}",
+            match difference.change {
+                Change::Added => Green.paint(line),
+                Change::Removed => Red.paint(line),
+                Change::Changed => Yellow.paint(line),
+            }
+        );
+    }
+
+    if !yes && !confirm(&format!("Apply changes to {}?", path.display()))? {
+        println!("Canceled");
+        return Ok(());
+    }
+    let backup = apply_pulled_app_config(path, pulled)?;
+    println!("Updated {:}", path.to_string_lossy());
+    println!("Backup: {:}", backup.to_string_lossy());
+    Ok(())
+}
+
+/// Parse deploy target given as `<target>[:<os>]=<path>`. OS is `default` if omitted.
+fn parse_target(spec: &str) -> Result<(String, String, String)> {
+    let (target, path) = spec.split_once('=').with_context(|| {
+        format!(
+            "Invalid deploy target {}. Use <target>[:<os>]=<path>.",
+            spec
+        )
+    })?;
+    let (target, os) = target.split_once(':').unwrap_or((target, "default"));
+    if target.is_empty() || path.is_empty() {
+        bail!(
+            "Invalid deploy target {}. Use <target>[:<os>]=<path>.",
+            spec
+        );
+    }
+    Ok((target.to_string(), os.to_string(), path.to_string()))
+}
+
+pub fn repo_add<P>(path: P, name: &str, url: &str, targets: &[String]) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    log::trace!(
+        "repo_add({:?}, {}, {}, {:?})",
+        path.as_ref(),
+        name,
+        url,
+        targets
+    );
+
+    let path = path.as_ref();
+    let mut repo = Repository {
+        name: name.to_string(),
+        url: Some(url.to_string()),
+        ..Repository::default()
+    };
+    for spec in targets {
+        let (target, os, to) = parse_target(spec)?;
+        repo.deploy.entry(target).or_default().path.set(&os, to)?;
+    }
+    add_repository(path, &repo)?;
+    println!(
+        "Added repository {:} to {:}",
+        Green.paint(name),
+        path.to_string_lossy()
+    );
+    Ok(())
+}
+
+pub fn repo_remove<P>(path: P, name: &str) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    log::trace!("repo_remove({:?}, {})", path.as_ref(), name);
+
+    let source = remove_repository(path, name)?;
+    println!(
+        "Removed repository {:} from {:}",
+        Green.paint(name),
+        source.to_string_lossy()
+    );
+    Ok(())
+}
+
+pub fn repo_list(files: &ConfigFiles) -> Result<()> {
+    log::trace!("repo_list({:?})", files);
+
+    let path = files.primary();
+    let (app_config, sources) = files.load_with_sources()?;
+    for repo in &app_config.repos {
+        match repo.source() {
+            Result::Ok(source) => print!("{:} {:}", Green.paint(&repo.name), source),
+            Err(_) => print!("{:}", Green.paint(&repo.name)),
+        }
+        match sources.get(&repo.name) {
+            Some(source) if source != path => {
+                println!(
+                    " {}",
+                    Fixed(8).paint(format!("({})", source.to_string_lossy()))
+                );
+            }
+            _ => println!(),
+        }
+    }
+    Ok(())
+}
+
+/// Print config file. With `resolved`, print the config as loaded,
+/// with config files, the local override, included files and environment variables merged.
+pub fn show(files: &ConfigFiles, resolved: bool) -> Result<()> {
+    log::trace!("show({:?}, {:?})", files, resolved);
+
+    if !resolved {
+        let path = files.primary();
+        print!(
+            "{}",
+            std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?
+        );
+        return Ok(());
+    }
+    let mut app_config = files.load()?;
+    // included repositories are already merged
+    app_config.include.clear();
+    print!("{}", Format::Yaml.serialize(&app_config)?);
+    Ok(())
+}
+
+/// Print config files in order of loading. Later files take precedence.
+pub fn config_path(files: &ConfigFiles) -> Result<()> {
+    log::trace!("config_path({:?})", files);
+
+    for file in &files.files {
+        let note = if file.path.exists() {
+            file.layer.to_string()
+        } else {
+            format!("{}, not found", file.layer)
+        };
+        println!(
+            "{} {}",
+            file.path.to_string_lossy(),
+            Fixed(8).paint(format!("({})", note))
+        );
+        if let Some(local) = local_path(&file.path)? {
+            println!(
+                "{} {}",
+                local.to_string_lossy(),
+                Fixed(8).paint(format!("({}, local)", file.layer))
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("home=/home/example", ("home", "default", "/home/example"))]
+    #[case("home:linux=${HOME}", ("home", "linux", "${HOME}"))]
+    #[case(".config:unix=a=b", (".config", "unix", "a=b"))]
+    fn test_parse_target(#[case] spec: &str, #[case] expected: (&str, &str, &str)) {
+        let (target, os, path) = parse_target(spec).unwrap();
+        assert_eq!((target.as_str(), os.as_str(), path.as_str()), expected);
+    }
+
+    #[rstest]
+    #[case("home")]
+    #[case("=/home/example")]
+    #[case("home=")]
+    fn test_parse_target_error(#[case] spec: &str) {
+        assert!(parse_target(spec).is_err());
+    }
+}
@@ -0,0 +1,442 @@
+//! Conditions of repositories and deploy targets.
+//!
+//! A condition is an expression evaluated on the running machine.
+//! - `env(NAME)`: environment variable `NAME` is set
+//! - `env(NAME) == value`, `env(NAME) != value`: environment variable `NAME` equals to `value`
+//! - `command(name)`: executable command `name` exists on `PATH`
+//! - `exists(path)`: file or directory exists. Variables in the path are expanded.
+//! - `hostname(pattern)`: host name matches the pattern. See [`crate::host::matches`].
+//! - `os(name)`: running on the OS. `name` is a key of OS path, e.g. `linux`, `unix` or `wsl`.
+//! - `arch(name)`: CPU architecture is `name`, e.g. `x86_64` or `aarch64`.
+//! - `true`, `false`
+//! - `!expr`, `expr && expr`, `expr || expr`, `(expr)`. `not`, `and` and `or` are also available.
+//!
+//! Arguments are quoted with `"` or `'`, or written as is if they have no space or symbol.
+use crate::config::os_keys;
+use crate::host;
+use crate::path::expand_var;
+use anyhow::{bail, ensure, Context as _, Result};
+use indexmap::IndexMap;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// Machine the conditions are evaluated on.
+pub struct Context<'a> {
+    vars: &'a IndexMap<String, String>,
+    hostname: String,
+    os_keys: &'static [&'static str],
+    arch: &'static str,
+    /// Lookup of environment variables.
+    env: fn(&str) -> Option<OsString>,
+}
+
+impl<'a> Context<'a> {
+    /// Context of the running machine. `vars` are expanded in paths.
+    pub fn new(vars: &'a IndexMap<String, String>) -> Self {
+        Self {
+            vars,
+            hostname: host::hostname(),
+            os_keys: os_keys(host::is_wsl()),
+            arch: std::env::consts::ARCH,
+            env: |name| std::env::var_os(name),
+        }
+    }
+}
+
+/// Evaluate the condition.
+pub fn evaluate(expr: &str, context: &Context) -> Result<bool> {
+    parse(expr)?
+        .evaluate(context)
+        .with_context(|| format!("Failed to evaluate condition `{}`", expr))
+}
+
+/// Parse the condition.
+pub fn parse(expr: &str) -> Result<Expr> {
+    (|| {
+        let mut parser = Parser {
+            tokens: tokenize(expr)?,
+            pos: 0,
+        };
+        let parsed = parser.or()?;
+        if let Some(token) = parser.peek() {
+            bail!("Unexpected {}", token);
+        }
+        Ok(parsed)
+    })()
+    .with_context(|| format!("Invalid condition `{}`", expr))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    Env,
+    Command,
+    Exists,
+    Hostname,
+    Os,
+    Arch,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Expr {
+    Bool(bool),
+    Call(Func, String),
+    /// Environment variable equals to the value.
+    EnvEq(String, String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn evaluate(&self, context: &Context) -> Result<bool> {
+        Ok(match self {
+            Expr::Bool(value) => *value,
+            Expr::Call(func, arg) => match func {
+                Func::Env => (context.env)(arg).is_some(),
+                Func::Command => command_exists(arg, context.env),
+                Func::Exists => Path::new(&expand_var(arg, |name| {
+                    Ok(context.vars.get(name).cloned())
+                })?)
+                .exists(),
+                Func::Hostname => host::matches(arg, &context.hostname),
+                Func::Os => arg != "default" && context.os_keys.contains(&arg.as_str()),
+                Func::Arch => context.arch == arg,
+            },
+            Expr::EnvEq(name, value) => (context.env)(name) == Some(OsString::from(value)),
+            Expr::Not(expr) => !expr.evaluate(context)?,
+            Expr::And(lhs, rhs) => lhs.evaluate(context)? && rhs.evaluate(context)?,
+            Expr::Or(lhs, rhs) => lhs.evaluate(context)? || rhs.evaluate(context)?,
+        })
+    }
+}
+
+/// Check if the command exists on `PATH`, looked up by `env`.
+fn command_exists<F>(name: &str, env: F) -> bool
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    if name.contains(['/', '\\']) {
+        return is_executable(Path::new(name));
+    }
+    let Some(paths) = env("PATH") else {
+        return false;
+    };
+    // executable extensions on Windows
+    let extensions = if cfg!(target_os = "windows") {
+        env("PATHEXT")
+            .and_then(|exts| exts.into_string().ok())
+            .unwrap_or(".COM;.EXE;.BAT;.CMD".to_string())
+            .split(';')
+            .map(str::to_string)
+            .collect()
+    } else {
+        vec![]
+    };
+    std::env::split_paths(&paths).any(|dir| {
+        is_executable(&dir.join(name))
+            || extensions
+                .iter()
+                .any(|ext| is_executable(&dir.join(format!("{}{}", name, ext))))
+    })
+}
+
+/// Check if the path is a file executable by someone, as `which` does.
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    {
+        path.is_file()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    LParen,
+    RParen,
+    Not,
+    And,
+    Or,
+    Eq,
+    Ne,
+    Word(String),
+    Quoted(String),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::LParen => write!(f, "`(`"),
+            Token::RParen => write!(f, "`)`"),
+            Token::Not => write!(f, "`!`"),
+            Token::And => write!(f, "`&&`"),
+            Token::Or => write!(f, "`||`"),
+            Token::Eq => write!(f, "`==`"),
+            Token::Ne => write!(f, "`!=`"),
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Quoted(s) => write!(f, "\"{}\"", s),
+        }
+    }
+}
+
+fn tokenize(expr: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = expr.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => tokens.push(Token::LParen),
+            ')' => tokens.push(Token::RParen),
+            '!' if chars.peek() == Some(&'=') => {
+                chars.next();
+                tokens.push(Token::Ne);
+            }
+            '!' => tokens.push(Token::Not),
+            '=' | '&' | '|' => {
+                ensure!(chars.next() == Some(c), "Unexpected `{}`", c);
+                tokens.push(match c {
+                    '=' => Token::Eq,
+                    '&' => Token::And,
+                    _ => Token::Or,
+                });
+            }
+            '"' | '\'' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some(q) if q == c => break,
+                        Some(ch) => s.push(ch),
+                        None => bail!("Unterminated string"),
+                    }
+                }
+                tokens.push(Token::Quoted(s));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&ch) = chars.peek() {
+                    if ch.is_whitespace() || "()!=&|\"'".contains(ch) {
+                        break;
+                    }
+                    word.push(ch);
+                    chars.next();
+                }
+                tokens.push(match word.as_str() {
+                    "not" => Token::Not,
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    _ => Token::Word(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<()> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => bail!("Expected {}, found {}", expected, token),
+            None => bail!("Expected {}", expected),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Word(word)) if word == "true" => Ok(Expr::Bool(true)),
+            Some(Token::Word(word)) if word == "false" => Ok(Expr::Bool(false)),
+            Some(Token::Word(name)) => {
+                let func = match name.as_str() {
+                    "env" => Func::Env,
+                    "command" => Func::Command,
+                    "exists" => Func::Exists,
+                    "hostname" => Func::Hostname,
+                    "os" => Func::Os,
+                    "arch" => Func::Arch,
+                    _ => bail!("Unknown function `{}`", name),
+                };
+                self.expect(Token::LParen)?;
+                let arg = self.arg()?;
+                self.expect(Token::RParen)?;
+
+                let negate = match self.peek() {
+                    Some(Token::Eq) => false,
+                    Some(Token::Ne) => true,
+                    _ => return Ok(Expr::Call(func, arg)),
+                };
+                ensure!(func == Func::Env, "Only `env` can be compared");
+                self.next();
+                let expr = Expr::EnvEq(arg, self.arg()?);
+                Ok(if negate {
+                    Expr::Not(Box::new(expr))
+                } else {
+                    expr
+                })
+            }
+            Some(token) => bail!("Unexpected {}", token),
+            None => bail!("Unexpected end of condition"),
+        }
+    }
+
+    fn arg(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Word(s) | Token::Quoted(s)) => Ok(s),
+            Some(token) => bail!("Expected argument, found {}", token),
+            None => bail!("Expected argument"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    fn context(vars: &IndexMap<String, String>) -> Context<'_> {
+        Context {
+            vars,
+            hostname: "build01.example.com".to_string(),
+            os_keys: &["linux", "unix", "default"],
+            arch: "x86_64",
+            env,
+        }
+    }
+
+    /// Environment with `RRCM_TEST_WHEN=work`, falling back to the process.
+    fn env(name: &str) -> Option<OsString> {
+        match name {
+            "RRCM_TEST_WHEN" => Some(OsString::from("work")),
+            _ => std::env::var_os(name),
+        }
+    }
+
+    #[rstest]
+    #[case("true", true)]
+    #[case("false", false)]
+    #[case("env(RRCM_TEST_WHEN)", true)]
+    #[case("env(RRCM_TEST_WHEN_UNDEFINED)", false)]
+    #[case("env(RRCM_TEST_WHEN) == work", true)]
+    #[case("env(RRCM_TEST_WHEN) == 'home'", false)]
+    #[case("env(RRCM_TEST_WHEN) != \"home\"", true)]
+    #[case("hostname(build*)", true)]
+    #[case("hostname('laptop')", false)]
+    #[case("os(linux)", true)]
+    #[case("os(unix)", true)]
+    #[case("os(windows)", false)]
+    #[case("os(default)", false)]
+    #[case("arch(x86_64)", true)]
+    #[case("arch(aarch64)", false)]
+    #[case("!os(windows)", true)]
+    #[case("not os(windows)", true)]
+    #[case("os(linux) && arch(aarch64)", false)]
+    #[case("os(linux) and arch(x86_64)", true)]
+    #[case("os(windows) || arch(x86_64)", true)]
+    #[case("os(windows) or arch(aarch64)", false)]
+    #[case("true || false && false", true)]
+    #[case("(true || false) && false", false)]
+    #[case("!(os(linux) && hostname(laptop))", true)]
+    #[case("exists(${RRCM_TEST_WHEN_DIR})", true)]
+    #[case("exists('${RRCM_TEST_WHEN_DIR}/missing')", false)]
+    fn test_evaluate(#[case] expr: &str, #[case] expected: bool) {
+        let vars = IndexMap::from([(
+            "RRCM_TEST_WHEN_DIR".to_string(),
+            std::env::temp_dir().to_string_lossy().to_string(),
+        )]);
+        assert_eq!(evaluate(expr, &context(&vars)).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_command_exists() {
+        let vars = IndexMap::new();
+        let context = context(&vars);
+        assert!(evaluate("command(git)", &context).unwrap());
+        assert!(!evaluate("command(rrcm-no-such-command)", &context).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_command_not_executable() -> anyhow::Result<()> {
+        use assert_fs::prelude::*;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = assert_fs::TempDir::new()?;
+        let command = temp.child("rrcm-test-command");
+        command.write_str("#!/bin/sh\n")?;
+        std::fs::set_permissions(command.path(), std::fs::Permissions::from_mode(0o644))?;
+        let name = command.path().to_string_lossy();
+        let path = |name: &str| (name == "PATH").then(|| temp.path().as_os_str().to_owned());
+        assert!(!command_exists(&name, env));
+        assert!(!command_exists("rrcm-test-command", path));
+        std::fs::set_permissions(command.path(), std::fs::Permissions::from_mode(0o755))?;
+        assert!(command_exists(&name, env));
+        assert!(command_exists("rrcm-test-command", path));
+        assert!(!command_exists("rrcm-test-command", |_| None));
+        Ok(())
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("env")]
+    #[case("env(")]
+    #[case("env(A")]
+    #[case("env(A) ==")]
+    #[case("os(linux) == linux")]
+    #[case("unknown(A)")]
+    #[case("true false")]
+    #[case("true & false")]
+    #[case("env('A)")]
+    #[case("(true")]
+    fn test_parse_error(#[case] expr: &str) {
+        assert!(parse(expr).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use ansi_term::Colour::{Green, Red, Yellow};
+use ansi_term::Colour::{Fixed, Green, Red, Yellow};
 use anyhow::Result;
 use assert_cmd::Command;
 use assert_fs::fixture::ChildPath;
@@ -10,9 +10,12 @@ use predicates::prelude::*;
 use rrcm::config::AppConfig;
 use rrcm::config::OsPath;
 use rrcm::config::Repository;
+use rrcm::config::{DeployMode, DeployTarget};
 use rstest::rstest;
 use std::fs;
 use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Arc, Mutex};
 
 // for cross
 fn find_runner() -> Option<String> {
@@ -45,6 +48,17 @@ fn create_temp_dir() -> Result<TempDir> {
     Ok(TempDir::new()?.into_persistent_if(false))
 }
 
+/// Path under `root` on every OS.
+fn os_path(root: &std::path::Path, path: &str) -> OsPath {
+    let root = root.to_string_lossy();
+    OsPath {
+        windows: Some(format!("{}\\{}", root, path)),
+        mac: Some(format!("{}/{}", root, path)),
+        linux: Some(format!("{}/{}", root, path)),
+        ..OsPath::default()
+    }
+}
+
 fn create_app_config(
     temp: &assert_fs::TempDir,
     repos: &IndexMap<String, String>,
@@ -56,6 +70,7 @@ fn create_app_config(
         windows: Some(format!("{}\\dotfiles", tmpdir)),
         mac: Some(format!("{}/dotfiles", tmpdir)),
         linux: Some(format!("{}/dotfiles", tmpdir)),
+        ..OsPath::default()
     };
 
     fs::create_dir(temp.path().join("home"))?;
@@ -68,26 +83,31 @@ fn create_app_config(
             .iter()
             .map(|(name, url)| Repository {
                 name: name.clone(),
-                url: url.clone(),
+                url: Some(url.clone()),
                 deploy: indexmap!(
                     String::from("home") => OsPath {
                         windows: Some(format!("{}\\home",tmpdir)),
                         mac: Some(format!("{}/home",tmpdir)),
                         linux: Some(format!("{}/home",tmpdir)),
-                    },
+                        ..OsPath::default()
+                    }.into(),
                     String::from("config") => OsPath {
                         windows: Some(format!("{}\\config",tmpdir)),
                         mac: Some(format!("{}/config",tmpdir)),
                         linux: Some(format!("{}/config",tmpdir)),
-                    },
+                        ..OsPath::default()
+                    }.into(),
                     String::from("config_local") => OsPath {
                         windows: Some(format!("{}\\config_local",tmpdir)),
                         mac: Some(format!("{}/config_local",tmpdir)),
                         linux: Some(format!("{}/config_local",tmpdir)),
-                    },
+                        ..OsPath::default()
+                    }.into(),
                 ),
+                ..Repository::default()
             })
             .collect(),
+        ..AppConfig::default()
     })?)?;
 
     Ok(config_file)
@@ -220,6 +240,763 @@ fn test_init_url(
     Ok(())
 }
 
+enum Route {
+    File(Vec<u8>),
+    Redirect(String),
+}
+
+/// Local HTTP server.
+struct Server {
+    url: String,
+    /// Request line and headers of each request in lower case.
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+/// Serve the routes on a local HTTP server.
+fn serve(routes: IndexMap<&'static str, Route>) -> Result<Server> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let url = format!("http://{}", listener.local_addr()?);
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let recorded = requests.clone();
+    std::thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap() > 2 {
+                request.push_str(&line.to_lowercase());
+                line.clear();
+            }
+
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+            let (status, location, body) = match routes.get(path) {
+                Some(Route::File(body)) => ("200 OK", String::new(), body.as_slice()),
+                Some(Route::Redirect(to)) => {
+                    ("302 Found", format!("Location: {}\r\n", to), b"".as_slice())
+                }
+                None => ("404 Not Found", String::new(), b"".as_slice()),
+            };
+            recorded.lock().unwrap().push(request.clone());
+            write!(
+                stream,
+                "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                location,
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(body).unwrap();
+        }
+    });
+    Ok(Server { url, requests })
+}
+
+#[test]
+fn test_init_url_sha256() -> Result<()> {
+    use sha2::{Digest, Sha256};
+
+    let temp = create_temp_dir()?;
+    let content = "version: 1\ndotfiles:\n  default: /dotfiles\nrepos: []\n";
+    let url = format!(
+        "{}/config.yaml",
+        serve(indexmap! {"/config.yaml" => Route::File(content.as_bytes().to_vec())})?.url
+    );
+    let sha256 = format!("{:x}", Sha256::digest(content));
+    let config_file = temp.child("config.yaml");
+    let init = |sha256: &str| -> Result<Command> {
+        let mut cmd = create_cmd_base(false, false, false, false)?;
+        cmd.arg("--config").arg(config_file.path());
+        cmd.args(["init", &url, "--sha256", sha256]);
+        Ok(cmd)
+    };
+
+    // nothing is written on mismatch
+    init(&"0".repeat(64))?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("SHA-256 mismatch."));
+    config_file.assert(predicate::path::missing());
+    temp.child("config.source.yaml")
+        .assert(predicate::path::missing());
+
+    init(&sha256)?.assert().success();
+    config_file.assert(content);
+    let source = rrcm::config::ConfigSource::read(config_file.path())?.unwrap();
+    pretty_assertions::assert_eq!(source.url, url);
+    pretty_assertions::assert_eq!(source.sha256, sha256);
+
+    temp.close()?;
+    Ok(())
+}
+
+#[rstest]
+#[case::header(&["-H", "Authorization: Bearer from-header", "-H", "X-Extra: 1"], None, "authorization: bearer from-header")]
+#[case::token(&[], Some(("RRCM_TOKEN", "from-env")), "authorization: bearer from-env")]
+#[case::token_env(&["--token-env", "GIST_TOKEN"], Some(("GIST_TOKEN", "from-gist")), "authorization: bearer from-gist")]
+#[case::netrc(&[], None, "authorization: basic cnjjbtpzzwnyzxq=")]
+fn test_init_url_auth(
+    #[case] args: &[&str],
+    #[case] env: Option<(&str, &str)>,
+    #[case] expected: &str,
+) -> Result<()> {
+    let temp = create_temp_dir()?;
+    let content = "version: 1\ndotfiles:\n  default: /dotfiles\nrepos: []\n";
+    // other host receiving the redirect
+    let mirror = serve(indexmap! {"/config.yaml" => Route::File(content.as_bytes().to_vec())})?;
+    let origin = serve(indexmap! {
+        "/config.yaml" => Route::Redirect(format!("{}/config.yaml", mirror.url)),
+    })?;
+    let netrc = temp.child("netrc");
+    netrc.write_str("machine 127.0.0.1 login rrcm password secret\n")?;
+
+    let config_file = temp.child("config.yaml");
+    let mut cmd = create_cmd_base(false, false, false, false)?;
+    cmd.env_remove("RRCM_TOKEN").env("NETRC", netrc.path());
+    if let Some((name, value)) = env {
+        cmd.env(name, value);
+    }
+    cmd.arg("--config").arg(config_file.path());
+    cmd.args(["init", &format!("{}/config.yaml", origin.url)]);
+    cmd.args(args);
+    cmd.assert().success();
+    config_file.assert(content);
+
+    let requests = origin.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(requests[0].contains(expected), "{}", requests[0]);
+    // credentials are not sent to the redirected host
+    let requests = mirror.requests.lock().unwrap();
+    assert_eq!(requests.len(), 1);
+    assert!(!requests[0].contains("authorization"), "{}", requests[0]);
+    assert!(!requests[0].contains("x-extra"), "{}", requests[0]);
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_init_local() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let content = "version: 1\ndotfiles:\n  default: /dotfiles\nrepos: []\n";
+    let usb = temp.child("usb/config.yaml");
+    usb.write_str(content)?;
+    let init = |config_file: &ChildPath, source: &str| -> Result<Command> {
+        let mut cmd = create_cmd_base(false, false, false, false)?;
+        cmd.arg("--config").arg(config_file.path());
+        cmd.args(["init", source]);
+        Ok(cmd)
+    };
+
+    // local path
+    let config_file = temp.child("path/config.yaml");
+    init(&config_file, &usb.path().to_string_lossy())?
+        .assert()
+        .success();
+    config_file.assert(content);
+    let source = rrcm::config::ConfigSource::read(config_file.path())?.unwrap();
+    pretty_assertions::assert_eq!(
+        source.url,
+        dunce::canonicalize(usb.path())?.display().to_string()
+    );
+
+    // file url
+    let config_file = temp.child("url/config.yaml");
+    let url = url::Url::from_file_path(usb.path()).unwrap();
+    init(&config_file, url.as_str())?.assert().success();
+    config_file.assert(content);
+
+    // stdin is not recorded
+    let config_file = temp.child("stdin/config.yaml");
+    init(&config_file, "-")?
+        .write_stdin(content)
+        .assert()
+        .success();
+    config_file.assert(content);
+    temp.child("stdin/config.source.yaml")
+        .assert(predicate::path::missing());
+
+    // invalid config is not written
+    let config_file = temp.child("invalid/config.yaml");
+    init(&config_file, "-")?
+        .write_stdin("dotfiles: [\n")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "stdin is not a valid config file.",
+        ));
+    config_file.assert(predicate::path::missing());
+
+    temp.close()?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+#[test]
+fn test_config_discovery() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let write = |path: &str, repo: &str| {
+        temp.child(path).write_str(&formatdoc! {"
+            version: 1
+            dotfiles:
+              default: /dotfiles
+            repos:
+              - name: {repo}
+                url: https://example.com/{repo}.git
+                deploy: {{}}
+        "})
+    };
+    write("xdg/rrcm/config.yaml", "system")?;
+    write("home/.config/rrcm/config.yaml", "user")?;
+    write("project/.rrcm.yaml", "project")?;
+    let cwd = temp.child("project/sub");
+    cwd.create_dir_all()?;
+
+    let run = |args: &[&str]| {
+        let mut cmd = get_base_command();
+        cmd.current_dir(cwd.path())
+            .env_remove("RRCM_CONFIG")
+            .env("HOME", temp.path().join("home"))
+            .env("XDG_CONFIG_HOME", temp.path().join("home/.config"))
+            .env("XDG_CONFIG_DIRS", temp.path().join("xdg"))
+            .args(args);
+        cmd
+    };
+
+    let output = run(&["config", "path"]).output()?;
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout)?;
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    for (line, (path, layer)) in lines.iter().zip([
+        ("xdg/rrcm/config.yaml", "system"),
+        ("home/.config/rrcm/config.yaml", "user"),
+        ("project/.rrcm.yaml", "project"),
+    ]) {
+        assert!(line.starts_with(&temp.path().join(path).to_string_lossy().to_string()));
+        assert!(line.contains(&format!("({})", layer)));
+    }
+
+    run(&["repo", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "{} https://example.com/system.git",
+            Green.paint("system")
+        )))
+        .stdout(predicate::str::contains(format!(
+            "{} https://example.com/user.git",
+            Green.paint("user")
+        )))
+        .stdout(predicate::str::contains(format!(
+            "{} https://example.com/project.git",
+            Green.paint("project")
+        )));
+
+    // --config disables discovery
+    let config = temp.path().join("home/.config/rrcm/config.yaml");
+    run(&["--config", &config.to_string_lossy(), "config", "path"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(--config)"))
+        .stdout(predicate::str::contains(".rrcm.yaml").not());
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_config_local() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let config_file = temp.child("config.yaml");
+    config_file.write_str(&formatdoc! {"
+        version: 1
+        dotfiles:
+          linux: /dotfiles
+          mac: /dotfiles
+          windows: C:\\dotfiles
+        repos:
+          - name: example1
+            url: https://example.com/example1.git
+            deploy:
+              home:
+                default: /home
+              .config:
+                default: /config
+          - name: example2
+            url: https://example.com/example2.git
+            deploy: {{}}
+    "})?;
+    temp.child("config.local.yaml").write_str(&formatdoc! {"
+        dotfiles:
+          default: /work
+        repos:
+          - name: example1
+            deploy:
+              .config: ~
+          - name: example3
+            path: /opt/example3
+            deploy: {{}}
+    "})?;
+
+    let mut cmd = create_cmd(&config_file, "config", &None, false, false, false, false)?;
+    cmd.args(["show", "--resolved"])
+        .assert()
+        .success()
+        .stdout(formatdoc! {"
+            version: 1
+            dotfiles:
+              default: /work
+            repos:
+            - name: example1
+              url: https://example.com/example1.git
+              deploy:
+                home:
+                  default: /home
+            - name: example2
+              url: https://example.com/example2.git
+              deploy: {{}}
+            - name: example3
+              path: /opt/example3
+              deploy: {{}}
+        "});
+
+    let mut cmd = create_cmd(&config_file, "config", &None, false, false, false, false)?;
+    cmd.arg("show")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".config:"));
+
+    let mut cmd = create_cmd(&config_file, "config", &None, false, false, false, false)?;
+    cmd.arg("path")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "{} {}",
+            temp.path().join("config.local.yaml").to_string_lossy(),
+            Fixed(8).paint("(--config, local)")
+        )));
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_config_pull() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let usb = temp.child("usb/config.yaml");
+    usb.write_str(&formatdoc! {"
+        version: 1
+        dotfiles:
+          default: /dotfiles
+        repos:
+          - name: example1
+            url: https://example.com/example1.git
+            deploy:
+              home:
+                default: /home
+    "})?;
+    let config_file = temp.child("config.yaml");
+    let pull = || -> Result<Command> {
+        let mut cmd = create_cmd(&config_file, "config", &None, false, false, false, false)?;
+        cmd.arg("pull");
+        Ok(cmd)
+    };
+
+    // not initialized from a source
+    config_file.write_str("version: 1\ndotfiles:\n  default: /dotfiles\nrepos: []\n")?;
+    pull()?
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is not recorded."));
+    fs::remove_file(config_file.path())?;
+
+    let mut cmd = create_cmd(&config_file, "init", &None, false, false, false, false)?;
+    cmd.arg(usb.path()).assert().success();
+    pull()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("is up to date with"));
+
+    let changed = formatdoc! {"
+        version: 1
+        dotfiles:
+          default: /dotfiles
+        repos:
+          - name: example1
+            url: https://example.com/example1.git
+            deploy:
+              home:
+                default: /home2
+          - name: example2
+            url: https://example.com/example2.git
+            deploy: {{}}
+    "};
+    usb.write_str(&changed)?;
+
+    // canceled
+    pull()?
+        .write_stdin("n\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "  {}",
+            Yellow.paint("~ repos.example1.deploy.home")
+        )))
+        .stdout(predicate::str::contains(format!(
+            "  {}",
+            Green.paint("+ repos.example2")
+        )))
+        .stdout(predicate::str::contains("Canceled"));
+    config_file.assert(predicate::str::contains("/home2").not());
+
+    pull()?.arg("--yes").assert().success();
+    config_file.assert(changed.as_str());
+    temp.child("config.yaml.bak")
+        .assert(predicate::str::contains("/home2").not());
+    pull()?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("is up to date with"));
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_deploy_copy() -> Result<()> {
+    let temp = create_temp_dir()?;
+
+    temp.child("home").create_dir_all()?;
+    let source = temp.child("dotfiles/example/home/.profile");
+    source.write_str("export A=1\n")?;
+    temp.child("dotfiles/example/home/.vim/vimrc")
+        .write_str("set nocompatible\n")?;
+
+    let config_file = temp.child("config.yaml");
+    config_file.write_str(&serde_yml::to_string(&AppConfig {
+        dotfiles: os_path(temp.path(), "dotfiles"),
+        repos: vec![Repository {
+            name: String::from("example"),
+            path: Some(String::from("example")),
+            deploy: indexmap!(
+                String::from("home") => DeployTarget {
+                    path: os_path(temp.path(), "home"),
+                    mode: DeployMode::Copy,
+                    ..DeployTarget::default()
+                },
+            ),
+            ..Repository::default()
+        }],
+        ..AppConfig::default()
+    })?)?;
+    let cmd =
+        |subcommand: &str| create_cmd(&config_file, subcommand, &None, false, false, false, false);
+
+    cmd("deploy")?.assert().success();
+    let copy = temp.child("home/.profile");
+    assert!(!copy.path().is_symlink());
+    copy.assert("export A=1\n");
+    temp.child("home/.vim/vimrc").assert("set nocompatible\n");
+    temp.child("dotfiles/.rrcm-state.yaml")
+        .assert(predicate::path::exists());
+    cmd("status")?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Deployed").count(2));
+
+    // the repository is updated
+    source.write_str("export A=2\n")?;
+    cmd("status")?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Outdated"))
+        .stdout(predicate::str::contains("Modified").not());
+    cmd("deploy")?.assert().success();
+    copy.assert("export A=2\n");
+
+    // the copy is modified
+    copy.write_str("export A=3\n")?;
+    cmd("status")?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Modified"));
+    cmd("deploy")?
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Copy is modified"));
+    copy.assert("export A=3\n");
+    cmd("deploy")?.arg("--force").assert().success();
+    copy.assert("export A=2\n");
+
+    cmd("undeploy")?.assert().success();
+    copy.assert(predicate::path::missing());
+    temp.child("home/.vim").assert(predicate::path::missing());
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_deploy_hardlink() -> Result<()> {
+    let temp = create_temp_dir()?;
+
+    temp.child("home").create_dir_all()?;
+    let source = temp.child("dotfiles/example/home/.profile");
+    source.write_str("export A=1\n")?;
+
+    let config_file = temp.child("config.yaml");
+    config_file.write_str(&serde_yml::to_string(&AppConfig {
+        dotfiles: os_path(temp.path(), "dotfiles"),
+        repos: vec![Repository {
+            name: String::from("example"),
+            path: Some(String::from("example")),
+            deploy: indexmap!(
+                String::from("home") => DeployTarget {
+                    path: os_path(temp.path(), "home"),
+                    mode: DeployMode::Hardlink,
+                    ..DeployTarget::default()
+                },
+            ),
+            ..Repository::default()
+        }],
+        ..AppConfig::default()
+    })?)?;
+    let cmd =
+        |subcommand: &str| create_cmd(&config_file, subcommand, &None, false, false, false, false);
+
+    cmd("deploy")?.assert().success();
+    let link = temp.child("home/.profile");
+    assert!(!link.path().is_symlink());
+    source.write_str("export A=2\n")?;
+    link.assert("export A=2\n");
+    cmd("status")?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Deployed"));
+
+    // an editor replaces the file
+    fs::remove_file(link.path())?;
+    link.write_str("export A=3\n")?;
+    cmd("status")?
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Conflict"))
+        .stdout(predicate::str::contains("Hard link is broken."));
+    cmd("deploy")?.arg("--force").assert().success();
+    link.assert("export A=2\n");
+
+    // directories can not be hard linked
+    temp.child("dotfiles/example/home/.vim/vimrc").touch()?;
+    cmd("deploy")?
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("can not be hard linked"));
+    temp.child("home/.vim").assert(predicate::path::missing());
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_config_schema() -> Result<()> {
+    let mut cmd = get_base_command();
+    cmd.arg("config").arg("schema");
+    let output = cmd.assert().success().get_output().stdout.clone();
+
+    let schema: serde_json::Value = serde_json::from_slice(&output)?;
+    pretty_assertions::assert_eq!(schema["title"], "AppConfig");
+    Ok(())
+}
+
+#[test]
+fn test_config_validate() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let config_file = temp.child("config.yaml");
+
+    config_file.write_str("version: 1\ndotfiles:\n  default: /dotfiles\nrepos: []\n")?;
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.arg("config").arg("validate");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("No problems found"));
+
+    config_file.write_str("dotfiles:\n  default: /dotfiles\nrepos:\n  - name: [\n")?;
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.arg("config").arg("validate");
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains(format!(
+            "{}:5:",
+            config_file.path().to_string_lossy()
+        )))
+        .stderr(predicate::str::contains("1 error(s) found"));
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_config_migrate() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let config_file = temp.child("config.yaml");
+    let original = "# dotfiles\ndotfiles:\n  default: /dotfiles # shared\nrepos: []\n";
+
+    config_file.write_str(original)?;
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.arg("config").arg("migrate");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("0 -> 1: Add `version`."))
+        .stdout(predicate::str::contains("rewritten").not());
+    // comments are kept
+    config_file
+        .assert("# dotfiles\nversion: 1\ndotfiles:\n  default: /dotfiles # shared\nrepos: []\n");
+    temp.child("config.yaml.bak").assert(original);
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.arg("config").arg("migrate");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("is up to date (version 1)"));
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_config_convert() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let config_file = temp.child("config.yaml");
+    config_file.write_str("version: 1\ndotfiles:\n  default: /dotfiles\nrepos: []\n")?;
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.arg("config").arg("convert").arg("--to").arg("toml");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "Converted {} to {}",
+            config_file.path().to_string_lossy(),
+            temp.child("config.toml").path().to_string_lossy()
+        )));
+    config_file.assert(predicate::path::missing());
+    temp.child("config.yaml.bak")
+        .assert(predicate::path::exists());
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(temp.child("config.toml").path());
+    cmd.arg("config").arg("validate");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("No problems found"));
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(temp.child("config.toml").path());
+    cmd.arg("config").arg("convert").arg("--to").arg("xml");
+    cmd.assert().failure();
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_config_convert_local_override() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let config_file = temp.child("config.yaml");
+    config_file.write_str("version: 1\ndotfiles:\n  default: /dotfiles\nrepos: []\n")?;
+    let local_file = temp.child("config.local.yaml");
+    local_file.write_str("dotfiles:\n  default: /work/dotfiles\n")?;
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.arg("config").arg("convert").arg("--to").arg("toml");
+    cmd.assert().success();
+    local_file.assert(predicate::path::exists());
+
+    // the local override is still merged into the converted file
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(temp.child("config.toml").path());
+    cmd.arg("config").arg("show").arg("--resolved");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("/work/dotfiles"));
+
+    // more than one local override is ambiguous
+    temp.child("config.local.toml").write_str("")?;
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(temp.child("config.toml").path());
+    cmd.arg("config").arg("show").arg("--resolved");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("Local overrides"));
+
+    temp.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_repo_add_remove() -> Result<()> {
+    let temp = create_temp_dir()?;
+    let config_file = temp.child("config.yaml");
+    config_file.write_str(
+        "version: 1\n# dotfiles\ndotfiles:\n  default: /dotfiles\n# repositories\nrepos: []\n",
+    )?;
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.args([
+        "repo",
+        "add",
+        "example1",
+        "https://example.com/example1.git",
+    ]);
+    cmd.args([
+        "--target",
+        "home=/home/example",
+        "--target",
+        "home:linux=/home/linux",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Added repository"));
+    config_file.assert(predicate::str::contains(
+        "# repositories\nrepos:\n  - name: example1\n",
+    ));
+    config_file.assert(predicate::str::contains("linux: /home/linux\n"));
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.args(["repo", "list"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "{} https://example.com/example1.git",
+            Green.paint("example1")
+        )));
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.args(["repo", "remove", "example1"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Removed repository"));
+    config_file.assert(
+        "version: 1\n# dotfiles\ndotfiles:\n  default: /dotfiles\n# repositories\nrepos: []\n",
+    );
+
+    let mut cmd = get_base_command();
+    cmd.arg("--config").arg(config_file.path());
+    cmd.args(["repo", "remove", "example1"]);
+    cmd.assert().failure();
+
+    temp.close()?;
+    Ok(())
+}
+
 mod win_need_admin {
 
     use super::*;
@@ -807,7 +1584,11 @@ mod win_need_admin {
         );
 
         for (path, _) in &deploy_files {
-            OpenOptions::new().write(true).create(true).open(path)?;
+            OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
         }
 
         // update clone
@@ -862,4 +1643,726 @@ mod win_need_admin {
         temp.close()?;
         Ok(())
     }
+
+    #[test]
+    fn test_deploy_host_rule() -> Result<()> {
+        let temp = create_temp_dir()?;
+        let hostname = gethostname::gethostname().to_string_lossy().to_string();
+
+        temp.child("home").create_dir_all()?;
+        temp.child("dotfiles/local/home/.profile").touch()?;
+        temp.child("dotfiles/local/home-other/.gitconfig").touch()?;
+        temp.child("dotfiles/local/home-host/.gitconfig").touch()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![Repository {
+                name: String::from("local"),
+                url: Some(String::from("https://example.com/local.git")),
+                deploy: indexmap!(
+                    String::from("home") => os_path(temp.path(), "home").into(),
+                    String::from("home-other") => os_path(temp.path(), "home").into(),
+                ),
+                hosts: indexmap!(
+                    String::from("no-such-host-*") => indexmap!(
+                        String::from("home") => None,
+                    ),
+                    hostname.clone() => indexmap!(
+                        String::from("home-other") => None,
+                        String::from("home-host") => Some(os_path(temp.path(), "home").into()),
+                    ),
+                ),
+                ..Repository::default()
+            }],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "deploy", &None, false, false, false, false)?;
+        cmd.assert().success();
+        assert_symlink(
+            temp.path().join("home").join(".profile"),
+            temp.path().join("dotfiles/local/home/.profile"),
+        )?;
+        assert_symlink(
+            temp.path().join("home").join(".gitconfig"),
+            temp.path().join("dotfiles/local/home-host/.gitconfig"),
+        )?;
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "  Host rule {} matched {}",
+                hostname, hostname
+            )))
+            .stdout(predicate::str::contains("no-such-host-*").not());
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deploy_when() -> Result<()> {
+        let temp = create_temp_dir()?;
+
+        temp.child("home").create_dir_all()?;
+        temp.child("config").create_dir_all()?;
+        temp.child("dotfiles/local/home/.profile").touch()?;
+        temp.child("dotfiles/local/config/.gitconfig").touch()?;
+        temp.child("dotfiles/skipped/home/.bashrc").touch()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![
+                Repository {
+                    name: String::from("local"),
+                    url: Some(String::from("https://example.com/local.git")),
+                    when: Some(String::from("true")),
+                    deploy: indexmap!(
+                        String::from("home") => os_path(temp.path(), "home").into(),
+                        String::from("config") => DeployTarget {
+                            path: os_path(temp.path(), "config"),
+                            when: Some(String::from("os(no-such-os)")),
+                            ..DeployTarget::default()
+                        },
+                    ),
+                    ..Repository::default()
+                },
+                Repository {
+                    name: String::from("skipped"),
+                    url: Some(String::from("https://example.com/skipped.git")),
+                    when: Some(String::from("env(RRCM_TEST_NO_SUCH_VAR)")),
+                    deploy: indexmap!(
+                        String::from("home") => os_path(temp.path(), "home").into(),
+                    ),
+                    ..Repository::default()
+                },
+            ],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "deploy", &None, false, false, false, false)?;
+        cmd.assert().success();
+        assert_symlink(
+            temp.path().join("home").join(".profile"),
+            temp.path().join("dotfiles/local/home/.profile"),
+        )?;
+        temp.child("config/.gitconfig")
+            .assert(predicate::path::missing());
+        temp.child("home/.bashrc")
+            .assert(predicate::path::missing());
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(
+                "config (when `os(no-such-os)` is false)",
+            ))
+            .stdout(predicate::str::contains(
+                "skipped (when `env(RRCM_TEST_NO_SUCH_VAR)` is false)",
+            ));
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_local() -> Result<()> {
+        let temp = create_temp_dir()?;
+
+        temp.child("home").create_dir_all()?;
+        temp.child("dotfiles/overlay/home/.profile").touch()?;
+        temp.child("machine/home/.gitconfig").touch()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![
+                Repository {
+                    name: String::from("overlay"),
+                    path: Some(String::from("overlay")),
+                    deploy: indexmap!(
+                        String::from("home") => os_path(temp.path(), "home").into(),
+                    ),
+                    ..Repository::default()
+                },
+                Repository {
+                    name: String::from("machine"),
+                    path: Some(temp.path().join("machine").to_string_lossy().to_string()),
+                    deploy: indexmap!(
+                        String::from("home") => os_path(temp.path(), "home").into(),
+                    ),
+                    ..Repository::default()
+                },
+            ],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "update", &None, false, false, false, false)?;
+        cmd.assert().success();
+        assert_symlink(
+            temp.path().join("home").join(".profile"),
+            temp.path().join("dotfiles/overlay/home/.profile"),
+        )?;
+        assert_symlink(
+            temp.path().join("home").join(".gitconfig"),
+            temp.path().join("machine/home/.gitconfig"),
+        )?;
+        temp.child("dotfiles/overlay/.git")
+            .assert(predicate::path::missing());
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "  Local directory {}",
+                temp.path().join("machine").to_string_lossy()
+            )));
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_env_override() -> Result<()> {
+        let temp = create_temp_dir()?;
+
+        temp.child("home").create_dir_all()?;
+        for name in ["a", "b", "c"] {
+            temp.child(format!("ci/{}/home/.{}", name, name)).touch()?;
+        }
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: ["a", "b", "c"]
+                .iter()
+                .map(|name| Repository {
+                    name: name.to_string(),
+                    path: Some(name.to_string()),
+                    deploy: indexmap!(
+                        String::from("home") => os_path(temp.path(), "home").into(),
+                    ),
+                    ..Repository::default()
+                })
+                .collect(),
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = get_base_command();
+        cmd.env("RRCM_CONFIG", config_file.path())
+            .env("RRCM_DOTFILES", temp.path().join("ci"))
+            .env("RRCM_REPOS", "a, c")
+            .arg("deploy")
+            .assert()
+            .success();
+        assert_symlink(
+            temp.path().join("home/.a"),
+            temp.path().join("ci/a/home/.a"),
+        )?;
+        assert_symlink(
+            temp.path().join("home/.c"),
+            temp.path().join("ci/c/home/.c"),
+        )?;
+        temp.child("home/.b").assert(predicate::path::missing());
+
+        // flags take precedence over environment variables
+        let mut cmd = create_cmd(
+            &config_file,
+            "deploy",
+            &Some(String::from("b")),
+            false,
+            false,
+            false,
+            false,
+        )?;
+        cmd.env("RRCM_CONFIG", temp.path().join("missing.yaml"))
+            .env("RRCM_DOTFILES", temp.path().join("ci"))
+            .env("RRCM_REPOS", "a")
+            .assert()
+            .success();
+        assert_symlink(
+            temp.path().join("home/.b"),
+            temp.path().join("ci/b/home/.b"),
+        )?;
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deploy_relative_links() -> Result<()> {
+        let temp = create_temp_dir()?;
+        let root = temp.path().join("root");
+
+        temp.child("root/home/.config").create_dir_all()?;
+        temp.child("root/dotfiles/example/home/.profile")
+            .write_str("export A=1\n")?;
+        temp.child("root/dotfiles/example/config/nvim/init.lua")
+            .touch()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(&root, "dotfiles"),
+            relative_links: true,
+            repos: vec![Repository {
+                name: String::from("example"),
+                path: Some(String::from("example")),
+                deploy: indexmap!(
+                    String::from("home") => os_path(&root, "home").into(),
+                    String::from("config") => DeployTarget {
+                        path: os_path(&root, "home/.config"),
+                        relative_links: Some(false),
+                        ..DeployTarget::default()
+                    },
+                ),
+                ..Repository::default()
+            }],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "deploy", &None, false, false, false, false)?;
+        cmd.assert().success();
+        let profile = root.join("home/.profile");
+        pretty_assertions::assert_eq!(
+            fs::read_link(&profile)?,
+            std::path::Path::new("..")
+                .join("dotfiles")
+                .join("example")
+                .join("home")
+                .join(".profile")
+        );
+        assert_symlink(
+            root.join("home/.config/nvim"),
+            root.join("dotfiles/example/config/nvim"),
+        )?;
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Deployed").count(2))
+            .stdout(predicate::str::contains("Conflict").not());
+
+        // relative links keep working when the directories are moved together
+        let moved = temp.path().join("moved");
+        fs::rename(&root, &moved)?;
+        pretty_assertions::assert_eq!(
+            fs::read_to_string(moved.join("home/.profile"))?,
+            "export A=1\n"
+        );
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deploy_relative_links_symlinked_parent() -> Result<()> {
+        let temp = create_temp_dir()?;
+
+        // relative links are resolved from the real directory the destination points to
+        temp.child("real/deep/cfg").create_dir_all()?;
+        temp.child("home").create_dir_all()?;
+        temp.child("home/.config")
+            .symlink_to_dir(temp.path().join("real/deep/cfg"))?;
+        temp.child("dotfiles/example/config/app.conf")
+            .write_str("a = 1\n")?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            relative_links: true,
+            repos: vec![Repository {
+                name: String::from("example"),
+                path: Some(String::from("example")),
+                deploy: indexmap!(
+                    String::from("config") => os_path(temp.path(), "home/.config").into(),
+                ),
+                ..Repository::default()
+            }],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "deploy", &None, false, false, false, false)?;
+        cmd.assert().success();
+        pretty_assertions::assert_eq!(
+            fs::read_to_string(temp.path().join("home/.config/app.conf"))?,
+            "a = 1\n"
+        );
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Deployed").count(1));
+
+        // a link relative to the lexical parent dangles, and is a conflict
+        fs::remove_file(temp.path().join("home/.config/app.conf"))?;
+        temp.child("home/.config/app.conf").symlink_to_file(
+            std::path::Path::new("..")
+                .join("..")
+                .join("dotfiles")
+                .join("example")
+                .join("config")
+                .join("app.conf"),
+        )?;
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Symlink is broken."));
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deploy_recursive() -> Result<()> {
+        let temp = create_temp_dir()?;
+        let repo = |name: &str| Repository {
+            name: String::from(name),
+            path: Some(String::from(name)),
+            deploy: indexmap!(
+                String::from("config") => DeployTarget {
+                    path: os_path(temp.path(), "home/.config"),
+                    recursive: true,
+                    ..DeployTarget::default()
+                },
+            ),
+            ..Repository::default()
+        };
+
+        temp.child("home/.config/nvim/local.lua").touch()?;
+        temp.child("dotfiles/a/config/nvim/init.lua").touch()?;
+        temp.child("dotfiles/a/config/nvim/lua/a.lua").touch()?;
+        temp.child("dotfiles/b/config/nvim/lua/b.lua").touch()?;
+        temp.child("dotfiles/b/config/fish/config.fish").touch()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![repo("a"), repo("b")],
+            ..AppConfig::default()
+        })?)?;
+
+        let nvim = temp.path().join("home/.config/nvim");
+        let dotfiles = temp.path().join("dotfiles");
+
+        // existing directory is descended into, and lua is unfolded for b
+        let mut cmd = create_cmd(&config_file, "deploy", &None, false, false, false, false)?;
+        cmd.assert().success();
+        assert_symlink(
+            nvim.join("init.lua"),
+            dotfiles.join("a/config/nvim/init.lua"),
+        )?;
+        assert!(!nvim.join("lua").is_symlink());
+        assert_symlink(
+            nvim.join("lua/a.lua"),
+            dotfiles.join("a/config/nvim/lua/a.lua"),
+        )?;
+        assert_symlink(
+            nvim.join("lua/b.lua"),
+            dotfiles.join("b/config/nvim/lua/b.lua"),
+        )?;
+        assert_symlink(
+            temp.path().join("home/.config/fish"),
+            dotfiles.join("b/config/fish"),
+        )?;
+        assert!(nvim.join("local.lua").is_file());
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Deployed").count(4))
+            .stdout(predicate::str::contains("Conflict").not());
+
+        // lua is folded back into the link to the directory of a
+        let mut cmd = create_cmd(
+            &config_file,
+            "undeploy",
+            &Some(String::from("b")),
+            false,
+            false,
+            false,
+            false,
+        )?;
+        cmd.assert().success();
+        assert_symlink(nvim.join("lua"), dotfiles.join("a/config/nvim/lua"))?;
+        assert!(!temp.path().join("home/.config/fish").exists());
+
+        let mut cmd = create_cmd(
+            &config_file,
+            "undeploy",
+            &Some(String::from("a")),
+            false,
+            false,
+            false,
+            false,
+        )?;
+        cmd.assert().success();
+        assert!(nvim.join("lua").symlink_metadata().is_err());
+        assert!(nvim.join("init.lua").symlink_metadata().is_err());
+        assert!(nvim.join("local.lua").is_file());
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deploy_recursive_repo_dir() -> Result<()> {
+        let temp = create_temp_dir()?;
+        let repo = |name: &str, target: &str| Repository {
+            name: String::from(name),
+            path: Some(String::from(name)),
+            deploy: indexmap!(
+                String::from("config") => DeployTarget {
+                    path: os_path(temp.path(), target),
+                    recursive: true,
+                    ..DeployTarget::default()
+                },
+            ),
+            ..Repository::default()
+        };
+
+        temp.child("home/.config").create_dir_all()?;
+        temp.child("home/other").create_dir_all()?;
+        temp.child("work/a/config/nvim/init.lua").touch()?;
+        temp.child("work/a/config/nvim/lua/a.lua").touch()?;
+        temp.child("dotfiles/b/config/nvim/lua/b.lua").touch()?;
+        temp.child("dotfiles/c/config/c.conf").touch()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![
+                Repository {
+                    dir: Some(os_path(temp.path(), "work")),
+                    ..repo("a", "home/.config")
+                },
+                repo("b", "home/.config"),
+                repo("c", "home/other"),
+            ],
+            ..AppConfig::default()
+        })?)?;
+
+        let nvim = temp.path().join("home/.config/nvim");
+        let work = temp.path().join("work");
+
+        // the link into a outside the dotfiles directory is unfolded for b
+        let mut cmd = create_cmd(&config_file, "deploy", &None, false, false, false, false)?;
+        cmd.assert().success();
+        assert!(!nvim.is_symlink());
+        assert!(!nvim.join("lua").is_symlink());
+        assert_symlink(nvim.join("init.lua"), work.join("a/config/nvim/init.lua"))?;
+        assert_symlink(
+            nvim.join("lua/b.lua"),
+            temp.path().join("dotfiles/b/config/nvim/lua/b.lua"),
+        )?;
+
+        // directories deployed by other repositories are not folded
+        std::fs::remove_file(nvim.join("lua/b.lua"))?;
+        let mut cmd = create_cmd(
+            &config_file,
+            "undeploy",
+            &Some(String::from("c")),
+            false,
+            false,
+            false,
+            false,
+        )?;
+        cmd.assert().success();
+        assert!(!nvim.join("lua").is_symlink());
+
+        // folded back into the link into a
+        let mut cmd = create_cmd(
+            &config_file,
+            "undeploy",
+            &Some(String::from("b")),
+            false,
+            false,
+            false,
+            false,
+        )?;
+        cmd.assert().success();
+        assert_symlink(&nvim, work.join("a/config/nvim"))?;
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_deploy_recursive_foreign_link() -> Result<()> {
+        let temp = create_temp_dir()?;
+
+        // a link made by the user outside rrcm is not unfolded
+        temp.child("mine/nvim/local.lua").touch()?;
+        temp.child("home/.config").create_dir_all()?;
+        temp.child("home/.config/nvim")
+            .symlink_to_dir(temp.path().join("mine/nvim"))?;
+        temp.child("dotfiles/a/config/nvim/init.lua").touch()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![Repository {
+                name: String::from("a"),
+                path: Some(String::from("a")),
+                deploy: indexmap!(
+                    String::from("config") => DeployTarget {
+                        path: os_path(temp.path(), "home/.config"),
+                        recursive: true,
+                        ..DeployTarget::default()
+                    },
+                ),
+                ..Repository::default()
+            }],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Conflict"));
+
+        let mut cmd = create_cmd(&config_file, "deploy", &None, false, false, false, false)?;
+        cmd.assert().success();
+        assert_symlink(
+            temp.path().join("home/.config/nvim"),
+            temp.path().join("mine/nvim"),
+        )?;
+        assert!(temp
+            .path()
+            .join("mine/nvim/init.lua")
+            .symlink_metadata()
+            .is_err());
+
+        temp.close()?;
+        Ok(())
+    }
+
+    fn git(dir: &std::path::Path, args: &[&str]) -> Result<()> {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=rrcm", "-c", "user.email=rrcm@example.com"])
+            .args(args)
+            .current_dir(dir)
+            .status()?;
+        assert!(status.success());
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_pin() -> Result<()> {
+        let temp = create_temp_dir()?;
+
+        // upstream repository with branches main and dev
+        let origin = temp.child("origin");
+        origin.child("home/.main").touch()?;
+        git(origin.path(), &["init", "-q", "-b", "main"])?;
+        git(origin.path(), &["add", "."])?;
+        git(origin.path(), &["commit", "-q", "-m", "main"])?;
+        git(origin.path(), &["checkout", "-q", "-b", "dev"])?;
+        origin.child("home/.dev").touch()?;
+        git(origin.path(), &["add", "."])?;
+        git(origin.path(), &["commit", "-q", "-m", "dev"])?;
+        git(origin.path(), &["checkout", "-q", "main"])?;
+        temp.child("home").create_dir_all()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![Repository {
+                name: String::from("pinned"),
+                url: Some(origin.path().to_string_lossy().to_string()),
+                branch: Some(String::from("dev")),
+                deploy: indexmap!(
+                    String::from("home") => os_path(temp.path(), "home").into(),
+                ),
+                ..Repository::default()
+            }],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "update", &None, true, false, false, false)?;
+        cmd.assert().success();
+        assert_symlink(
+            temp.path().join("home").join(".dev"),
+            temp.path().join("dotfiles/pinned/home/.dev"),
+        )?;
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("  Pinned to branch dev"))
+            .stdout(predicate::str::contains("Diverged").not());
+
+        git(
+            &temp.path().join("dotfiles/pinned"),
+            &["checkout", "-q", "main"],
+        )?;
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains(format!(
+                "  {} on branch main, expected branch dev",
+                Yellow.paint("Diverged")
+            )));
+
+        // moved back to the pinned branch
+        let mut cmd = create_cmd(&config_file, "update", &None, true, false, false, false)?;
+        cmd.assert().success();
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Diverged").not());
+
+        temp.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_dir() -> Result<()> {
+        let temp = create_temp_dir()?;
+
+        let origin = temp.child("origin");
+        origin.child("home/.work").touch()?;
+        git(origin.path(), &["init", "-q", "-b", "main"])?;
+        git(origin.path(), &["add", "."])?;
+        git(origin.path(), &["commit", "-q", "-m", "work"])?;
+        temp.child("home").create_dir_all()?;
+
+        let config_file = temp.child("config.yaml");
+        config_file.write_str(&serde_yml::to_string(&AppConfig {
+            dotfiles: os_path(temp.path(), "dotfiles"),
+            repos: vec![Repository {
+                name: String::from("work"),
+                url: Some(origin.path().to_string_lossy().to_string()),
+                dir: Some(os_path(temp.path(), "secure")),
+                deploy: indexmap!(
+                    String::from("home") => os_path(temp.path(), "home").into(),
+                ),
+                ..Repository::default()
+            }],
+            ..AppConfig::default()
+        })?)?;
+
+        let mut cmd = create_cmd(&config_file, "update", &None, true, false, false, false)?;
+        cmd.assert().success();
+        temp.child("dotfiles/work")
+            .assert(predicate::path::missing());
+        assert_symlink(
+            temp.path().join("home").join(".work"),
+            temp.path().join("secure/work/home/.work"),
+        )?;
+
+        let mut cmd = create_cmd(&config_file, "status", &None, false, false, false, false)?;
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("Deployed"));
+
+        let mut cmd = create_cmd(&config_file, "undeploy", &None, true, false, false, false)?;
+        cmd.assert().success();
+        temp.child("home/.work").assert(predicate::path::missing());
+
+        temp.close()?;
+        Ok(())
+    }
 }